@@ -0,0 +1,87 @@
+// Renders a single frame without opening a window and saves it as a PNG.
+// e.g. raycaster-render --map res/images/map3.png --pos 13,4 --dir 1,0 -o out.png
+// It can also save the map's lightmap, with or without rendering anything (raycaster-render --map res/images/map4.png --export-lightmap light.png)
+
+use std::process::exit;
+
+use nalgebra::Vector2;
+use raycaster::{game::Game, renderer::{self, RenderConfig}};
+
+const USAGE: &str = "usage: raycaster-render [--map <map.png>] [--pos <x,y>] [--dir <x,y>] [--pitch <pitch>] [--size <w>x<h>] [--fov <fov>] [--show-map] [--banded-light] [--no-ao] [--flashlight] [--export-lightmap <lightmap.png>] -o <out.png>";
+
+fn main() {
+    let mut map_path = String::from("res/images/map3.png");
+    let mut pos = Vector2::new(13.0, 4.0);
+    let mut dir = Vector2::new(1.0, 0.0);
+    let mut pitch = 0.0;
+    let mut config = RenderConfig::default();
+    let mut show_map = false;
+    let mut flashlight = false;
+    let mut out_path: Option<String> = None;
+    let mut lightmap_path: Option<String> = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--map"      => map_path = next_value(&mut args, &arg),
+            "--pos"      => pos   = parse_vector(&next_value(&mut args, &arg)),
+            "--dir"      => dir   = parse_vector(&next_value(&mut args, &arg)),
+            "--pitch"    => pitch = parse_number(&next_value(&mut args, &arg)),
+            "--fov"      => config.fov = parse_number(&next_value(&mut args, &arg)),
+            "--size"     => {
+                let size = next_value(&mut args, &arg);
+                let Some((width, height)) = RenderConfig::parse_size(&size) else { fail(&format!("'{size}' should look like 480x240")) };
+                config = config.with_size(width, height, config.scale);
+            }
+            "--show-map" => show_map = true,
+            "--banded-light" => config.smooth_lighting = false,
+            "--no-ao" => config.ambient_occlusion = false,
+            "--flashlight" => flashlight = true,
+            "--export-lightmap" => lightmap_path = Some(next_value(&mut args, &arg)),
+            "-o" | "--out" => out_path = Some(next_value(&mut args, &arg)),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => fail(&format!("unknown argument '{arg}'")),
+        }
+    }
+
+    if out_path.is_none() && lightmap_path.is_none() { fail("no output path given") }
+    if dir.magnitude() == 0.0 { fail("--dir can't be 0,0"); }
+
+    let mut game = Game::from_map(&map_path).unwrap_or_else(|err| fail(&format!("couldn't load map '{map_path}': {err}")));
+    if let Some(lightmap_path) = lightmap_path {
+        if let Err(err) = game.map.save_lightmap(&lightmap_path) {
+            fail(&format!("couldn't save '{lightmap_path}': {err}"));
+        }
+    }
+    let Some(out_path) = out_path else { return };
+    game.player.flashlight = flashlight;
+    let frame = renderer::render_offscreen(&mut game, &config, pos, dir, pitch, show_map);
+    if let Err(err) = frame.save(&out_path) {
+        fail(&format!("couldn't save '{out_path}': {err}"));
+    }
+}
+
+fn next_value(args: &mut impl Iterator<Item = String>, flag: &str) -> String {
+    args.next().unwrap_or_else(|| fail(&format!("{flag} needs a value")))
+}
+
+fn parse_number(s: &str) -> f64 {
+    s.trim().parse().unwrap_or_else(|_| fail(&format!("'{s}' isn't a number")))
+}
+
+// Parses "x,y"
+fn parse_vector(s: &str) -> Vector2<f64> {
+    match s.split_once(',') {
+        Some((x, y)) => Vector2::new(parse_number(x), parse_number(y)),
+        None => fail(&format!("'{s}' should look like x,y")),
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("raycaster-render: {message}");
+    eprintln!("{USAGE}");
+    exit(1);
+}
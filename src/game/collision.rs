@@ -1,10 +1,11 @@
 use lerp::num_traits::float;
-use nalgebra::{point, Point1, Point2};
+use nalgebra::{point, Point2, Vector2};
 
 use crate::game::collision;
 
 use super::player::PLAYER_RADIUS;
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Segm {
     Line(f64, f64, f64, f64), // Pos A, Pos B
     Circle(f64, f64, f64), // Center, Radius
@@ -19,7 +20,7 @@ pub struct Collision {
     pub normal: Point2<f64>,
 }
 
-pub fn slide_mov(pos: &mut Point2<f64>, delta: Point2<f64>, segments: &Vec<Segment>) {
+pub fn slide_mov(pos: &mut Point2<f64>, delta: Point2<f64>, segments: &Vec<Segm>) {
     pos.x += delta.x;
     pos.y += delta.y;
 
@@ -34,10 +35,10 @@ pub fn slide_mov(pos: &mut Point2<f64>, delta: Point2<f64>, segments: &Vec<Segme
     }
 }
 
-pub fn collide_with_segments(pos: Point2<f64>, segments: &Vec<Segment>) -> Option<Collision> {
+pub fn collide_with_segments(pos: Point2<f64>, segments: &Vec<Segm>) -> Option<Collision> {
     let mut deepest: Option<Collision> = None;
     for seg in segments {
-        if let Some(collision) = collide_disk_with_segment(pos, *seg, &Segm::Line(0.0, 0.0, 1.0, 1.0)) {
+        if let Some(collision) = collide_disk_with_segment(pos, seg) {
             deepest = match &deepest {
                 None => Some(collision),
                 Some(d) => {
@@ -54,10 +55,10 @@ pub fn collide_with_segments(pos: Point2<f64>, segments: &Vec<Segment>) -> Optio
    deepest
 }
 
-pub fn collide_disk_with_segment(disk_center: Point2<f64>, seg: Segment, s2: &Segm) -> Option<Collision> {
-    match s2 {
+pub fn collide_disk_with_segment(disk_center: Point2<f64>, seg: &Segm) -> Option<Collision> {
+    match *seg {
         Segm::Line(x1, y1, x2, y2) => {
-            let delta = disk_center - closest_point_on_seg(disk_center, seg);
+            let delta = disk_center - closest_point_on_seg(disk_center, [point![x1, y1], point![x2, y2]]);
 
             if point_2_cmp_mul(delta.into(), delta.into()) > PLAYER_RADIUS * PLAYER_RADIUS { return None; }
 
@@ -65,7 +66,19 @@ pub fn collide_disk_with_segment(disk_center: Point2<f64>, seg: Segment, s2: &Se
             let n = delta * (1.0 / dist);
             Some(Collision { depth: PLAYER_RADIUS - dist, normal: n.into() })
         },
-        _ => None
+        // Pushes the player straight out from the middle of the circle
+        Segm::Circle(x, y, radius) => {
+            let delta = disk_center - point![x, y];
+            let dist = delta.magnitude();
+            if dist > radius + PLAYER_RADIUS { return None; }
+
+            // Right in the middle there's no way out that's better than any other, so just pick one
+            let n = match dist > 0.0 {
+                true  => delta / dist,
+                false => Vector2::new(1.0, 0.0),
+            };
+            Some(Collision { depth: radius + PLAYER_RADIUS - dist, normal: n.into() })
+        }
     }
 }
 
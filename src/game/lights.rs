@@ -0,0 +1,97 @@
+// Lights that move or change while the game's running, like the player's flashlight and muzzle flashes.
+// These don't go in the baked lightmap, they get spread out again every frame and added on top of it (see Map::update_dynamic_light)
+
+use nalgebra::Vector2;
+
+pub struct DynamicLight {
+    pub pos: Vector2<f64>,
+    // How bright each channel is, up to MAX_LIGHT
+    pub colour: [f64; 3],
+    // For spotlights, which way it's pointing and how wide the beam is (in radians either side of the middle)
+    pub cone: Option<(Vector2<f64>, f64)>,
+    // For lights that go away on their own, how many seconds it has left and how long it lasted to begin with
+    pub lifetime: Option<(f64, f64)>,
+}
+
+impl DynamicLight {
+    // A light that shines equally in every direction and stays until it's removed
+    pub fn point(pos: Vector2<f64>, colour: [f64; 3]) -> DynamicLight {
+        DynamicLight { pos, colour, cone: None, lifetime: None }
+    }
+
+    // A quick flash (like from a gun) that fades out over 'length' seconds
+    pub fn burst(pos: Vector2<f64>, colour: [f64; 3], length: f64) -> DynamicLight {
+        DynamicLight { pos, colour, cone: None, lifetime: Some((length, length)) }
+    }
+
+    // A torch pointing in 'dir'
+    pub fn spotlight(pos: Vector2<f64>, dir: Vector2<f64>, spread: f64, colour: [f64; 3]) -> DynamicLight {
+        DynamicLight { pos, colour, cone: Some((dir, spread)), lifetime: None }
+    }
+
+    // How bright it is right now, bursts get dimmer as they run out
+    pub fn brightness(&self) -> [f64; 3] {
+        match self.lifetime {
+            Some((left, length)) => self.colour.map(|c| c * (left / length).clamp(0.0, 1.0)),
+            None => self.colour,
+        }
+    }
+
+    // How much of the light reaches 'pos', 1 inside the beam fading out to 0 just outside it
+    pub fn cone_amount(&self, pos: Vector2<f64>) -> f64 {
+        let Some((dir, spread)) = self.cone else { return 1.0 };
+        let to = pos - self.pos;
+        // Anything right next to the torch gets lit whichever way it's pointing
+        if to.magnitude() < 0.75 { return 1.0; }
+        let angle = to.angle(&dir);
+        ((spread * 1.5 - angle) / (spread * 0.5)).clamp(0.0, 1.0)
+    }
+}
+
+// Lights on the map can change over time too, the lowest two bits of a light cell's flags say how
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LightAnimation {
+    Steady,
+    Flicker,
+    Strobe,
+}
+
+impl LightAnimation {
+    pub fn from_flags(flags: u8) -> LightAnimation {
+        match flags & 0b11 {
+            0b01 => LightAnimation::Flicker,
+            0b10 => LightAnimation::Strobe,
+            _ => LightAnimation::Steady,
+        }
+    }
+
+    // How bright the light is at 'time' from 0 to 1, 'seed' is so that lights next to each other don't all flicker together
+    pub fn brightness(&self, time: f64, seed: usize) -> f64 {
+        match self {
+            LightAnimation::Steady => 1.0,
+            // Mostly on but wobbling about, dropping out now and then like a dodgy bulb
+            LightAnimation::Flicker => {
+                let step = (time * 12.0) as u64;
+                let noise = hash(step ^ (seed as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+                match noise % 16 {
+                    0 => 0.15,
+                    n => 0.7 + (n as f64 / 16.0) * 0.3,
+                }
+            }
+            // On and off, twice a second
+            LightAnimation::Strobe => match (time * 2.0 + seed as f64 * 0.37).fract() < 0.5 {
+                true  => 1.0,
+                false => 0.1,
+            },
+        }
+    }
+}
+
+// Scrambles a number up so the flicker looks random but is always the same for the same time
+fn hash(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^ (x >> 33)
+}
@@ -1,22 +1,59 @@
 use std::collections::HashMap;
 
 use image;
-use nalgebra::{point, Vector2};
+use nalgebra::Vector2;
 
-use super::collision::Segment;
+use super::{collision::Segm, settings::MapSettings};
+use crate::util::shape::{Shape, ShapeRegistry, SHAPES_PATH};
+
+mod lightmap;
+
+pub use lightmap::{light_colour, MAX_LIGHT};
 
 // One byte for kind
 // One byte for any flags
 // One byte for texture index
+// One byte each for the floor and ceiling texture indices
+// One byte for how tall the walls are, in eighths of a block (so 8 is normal, 4 is a half height counter, 16 is twice as tall...)
 pub struct Cell {
     pub kind: u8,
     pub flags: u8,
     pub texture_index: u8,
+    pub floor_texture_index: u8,
+    pub ceiling_texture_index: u8,
+    pub height: u8,
 }
 
+// Default floor and ceiling textures, indices into Game::textures
+pub const FLOOR_TEXTURE_INDEX  : u8 = 4;
+pub const CEILING_TEXTURE_INDEX: u8 = 5;
+// Doorways get a different floor so they stand out a bit
+pub const DOOR_FLOOR_TEXTURE_INDEX: u8 = 6;
+pub const GRATE_TEXTURE_INDEX: u8 = 8;
+pub const HOLO_TEXTURE_INDEX : u8 = 9;
+
+pub const DEFAULT_HEIGHT: u8 = 8;
+
 impl Cell {
     pub fn new(kind: u8, flags: u8, texture_index: u8) -> Cell {
-        Cell { kind, flags, texture_index }
+        Cell { kind, flags, texture_index, floor_texture_index: FLOOR_TEXTURE_INDEX, ceiling_texture_index: CEILING_TEXTURE_INDEX, height: DEFAULT_HEIGHT }
+    }
+
+    // Height of the walls in blocks
+    pub fn wall_height(&self) -> f64 {
+        self.height as f64 / DEFAULT_HEIGHT as f64
+    }
+
+    // Full height solid blocks, light can't get into them at all (it can still get over shorter ones)
+    pub fn blocks_light(&self) -> bool {
+        self.kind == 1 && self.height >= DEFAULT_HEIGHT
+    }
+
+    // Gives the cell its own floor and ceiling textures
+    pub fn with_floor_and_ceiling(mut self, floor_texture_index: u8, ceiling_texture_index: u8) -> Cell {
+        self.floor_texture_index   = floor_texture_index;
+        self.ceiling_texture_index = ceiling_texture_index;
+        self
     }
 }
 
@@ -28,11 +65,23 @@ pub enum DoorState {
     Opening(f64),
 }
 
+impl DoorState {
+    // How open it is, from 0 (shut) to 1 (all the way open). The timers count down from 0.5
+    pub fn open_amount(&self) -> f64 {
+        match *self {
+            DoorState::Closed     => 0.0,
+            DoorState::Open(..)   => 1.0,
+            DoorState::Closing(a) => a * 2.0,
+            DoorState::Opening(a) => 1.0 - a * 2.0,
+        }
+    }
+}
+
 /* 
 Kinds             | Flags
 0 - Nothing       | None
 1 - Solid wall    | None
-2 - Light         | 00 00 00 rgb colours
+2 - Light         | 00 00 00 rgb colours (how bright each channel is, 0 to 3)
 3 - Door          | 0 (x/y), 0 (flipped), 00 type (slide, elevator1, elevator2, hinge (how the fuck will i do that then...))
 4 - Thin wall     | 0 direction
 5 - Thick wall    | 0 direction
@@ -41,14 +90,36 @@ Kinds             | Flags
 8 - Diagonal      | 0 direction (TL to BR, TR to BL) // maybe make it solid
 */
 
+// Offsets to the cells around a cell, sides first (right, left, down, up) then corners
+pub const NEIGHBOURS: [(isize, isize); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (-1, 1), (1, -1), (-1, -1)];
+
 pub struct Map {
     pub cells: Vec<Cell>,
     pub width : usize,
     pub height: usize,
+    // If you change these yourself rather than through update_doors(), call update_door_shapes() afterwards
     pub doors: HashMap<usize, DoorState>,
-    pub lightmap: Vec<u8>,
+    // What each door is made of right now, and how open it was when that got worked out. Kept so rays don't have to redo it every time they cross one
+    door_shapes: HashMap<usize, (f64, Vec<Shape>)>,
+    // How bright each cell is, as red, green and blue levels from 0 to 16
+    pub lightmap: Vec<[u8; 3]>,
+    // Extra light from dynamic lights for this frame, added on top of the lightmap (see Map::light)
+    pub dynamic_lightmap: Vec<[u8; 3]>,
+    // Which sides of each cell light can get between, see lightmap.rs
+    light_passages: Vec<u16>,
+    // Light painted on by hand (from the map settings), empty if there isn't any
+    painted_lightmap: Vec<[u8; 3]>,
+    // Just the light from the lights on the map, before the painted light gets mixed in. It's what save_lightmap() saves
+    baked_lightmap: Vec<[u8; 3]>,
+    // Height of the tallest wall on the map, rays can stop at walls this tall since nothing can poke out above them
+    pub tallest_wall: u8,
+    pub settings: MapSettings,
+    // What all the cells that aren't solid blocks or doors are made of
+    pub shapes: ShapeRegistry,
+    // Which of the 8 cells around each cell are full height solid blocks, as bits in the same order as NEIGHBOURS. Used for ambient occlusion
+    pub solid_neighbours: Vec<u8>,
 
-    pub collision: Vec<Segment>,
+    pub collision: Vec<Segm>,
 }
 
 impl Map {
@@ -63,8 +134,12 @@ impl Map {
         return &self.cells[index];
     }
 
-    pub fn load(image_path: String) -> Map {
-        let img = image::open(image_path).unwrap().to_rgb8();
+    // Fails if the image can't be opened
+    pub fn load(image_path: String) -> image::ImageResult<Map> {
+        // The alpha channel of each pixel is how tall its walls are, in eighths of a block.
+        // Fully opaque (or fully transparent) pixels are normal height walls, so maps that don't care about heights don't have to do anything
+        let settings = MapSettings::load(&image_path);
+        let img = image::open(&image_path)?.to_rgba8();
         let width  = img.width()  as usize;
         let height = img.height() as usize;
 
@@ -74,28 +149,49 @@ impl Map {
         let mut doors = HashMap::new();
 
         for (i, p) in img.pixels().enumerate() {
-            cells.push(match p.0 {
+            let [r, g, b, a] = p.0;
+            let cell_height = match a {
+                0 | 255 => DEFAULT_HEIGHT,
+                a => a,
+            };
+            let mut cell = match [r, g, b] {
                 // Solid - white
                 [255, 255, 255] => Cell::new(1, 0, 0),
                 // Light
                 [255, 255,   0] => Cell::new(2, 0b_11_11_11_00, 0),
+                // Red alarm light
+                [255,   0,   0] => Cell::new(2, 0b_11_00_00_00, 0),
+                // Blue terminal light
+                [  0, 128, 255] => Cell::new(2, 0b_01_10_11_00, 0),
+                // Flickering light
+                [200, 200,   0] => Cell::new(2, 0b_11_10_01_01, 0),
+                // Strobing alarm light
+                [200,   0,   0] => Cell::new(2, 0b_11_00_00_10, 0),
                 // Door NS
                 [127,  81,  25] => {
                     doors.insert(i, DoorState::Closed);
-                    Cell::new(3, 0b0000_00_00, 1)
+                    Cell::new(3, 0b0000_00_00, 1).with_floor_and_ceiling(DOOR_FLOOR_TEXTURE_INDEX, CEILING_TEXTURE_INDEX)
                 }
                 // Door WE
                 [204, 130,  40] => {
                     doors.insert(i, DoorState::Closed);
-                    Cell::new(3, 0b0000_00_11, 1)
+                    Cell::new(3, 0b0000_00_11, 1).with_floor_and_ceiling(DOOR_FLOOR_TEXTURE_INDEX, CEILING_TEXTURE_INDEX)
                 }
                 // Elevator door NS
                 [119, 119, 119] => {
                     doors.insert(i, DoorState::Closed);
-                    Cell::new(3, 0b0000_01_00, 2)
+                    Cell::new(3, 0b0000_01_00, 2).with_floor_and_ceiling(DOOR_FLOOR_TEXTURE_INDEX, CEILING_TEXTURE_INDEX)
                 }
                 // Thin wall Map
                 [177, 255,  61] => Cell::new(4, 0b0000000_1, 3),
+                // Grate NS
+                [150, 150, 150] => Cell::new(4, 0b0000000_0, GRATE_TEXTURE_INDEX),
+                // Grate EW
+                [100, 100, 100] => Cell::new(4, 0b0000000_1, GRATE_TEXTURE_INDEX),
+                // Hologram NS
+                [  0, 255, 255] => Cell::new(4, 0b0000000_0, HOLO_TEXTURE_INDEX),
+                // Hologram EW
+                [  0, 200, 200] => Cell::new(4, 0b0000000_1, HOLO_TEXTURE_INDEX),
                 // Thick wall NS
                 [188,  96, 188] => Cell::new(5, 0b0000000_0, 0),
                 // Thick wall EW
@@ -118,98 +214,126 @@ impl Map {
                 },
                 // Nothing
                 _ => Cell::new(0, 0, 0),
-            });
+            };
+            cell.height = cell_height;
+            cells.push(cell);
         }
-        let mut m = Map {cells, width, height, doors, lightmap: vec![], collision: vec![]};
+        let tallest_wall = cells.iter().filter(|c| c.kind != 0 && c.kind != 2).map(|c| c.height).max().unwrap_or(DEFAULT_HEIGHT);
+        let mut m = Map {cells, width, height, doors, door_shapes: HashMap::new(), lightmap: vec![], dynamic_lightmap: vec![], light_passages: vec![], painted_lightmap: vec![], baked_lightmap: vec![], tallest_wall, settings, shapes: ShapeRegistry::load(SHAPES_PATH), solid_neighbours: vec![], collision: vec![]};
+        m.load_painted_lightmap(&image_path);
         m.calculate_lightmap();
         m.calculate_collision();
-        m
+        m.calculate_solid_neighbours();
+        m.update_door_shapes();
+        Ok(m)
     }
 
-    pub fn calculate_lightmap(&mut self) {
-        self.lightmap = vec![0; self.width*self.height];
-        // Find where all of the lights are
-        let light_positions: Vec<usize> = self.cells.iter()
-            .enumerate()
-            .filter(|(_, item)| item.kind == 2)
-            .map(|(index, _)| index)
-            .collect();
-        
-        // For each light...
-        for lp in light_positions {
-            let mut light_level = 16;
-            
-            // Which cells this light has checked
-            let mut done: Vec<usize> = Vec::with_capacity(30);
-            // Positions we're currently checking
-            let mut fronteir: Vec<usize> = vec![lp];
-            // Holds all of the neighbours we're gonna do next.
-            let mut neighbours : Vec<usize> = Vec::with_capacity(30);
-
-            while light_level > 0 {
-                for &index in &fronteir {
-                    // Make sure we only do each cell once per light
-                    if done.contains(&index) {
-                        continue;
-                    }
-                    done.push(index);
+    // What's in a cell, with doors as open as they are right now
+    pub fn cell_shapes(&self, index: usize) -> &[Shape] {
+        match self.door_shapes.get(&index) {
+            Some((_, shapes)) => shapes,
+            None => self.shapes.get(self.cells[index].kind, self.cells[index].flags),
+        }
+    }
 
-                    // Set the light level of the cell (if it's higher than the old one)
-                    if self.lightmap[index] < light_level {
-                        self.lightmap[index] = light_level;
+    // Moves the doors on by 'deltatime' seconds
+    pub fn update_doors(&mut self, deltatime: f64) {
+        // Light only gets through doors that are all the way open, so the lightmap needs redoing around any that open or start closing
+        let mut changed_doors = Vec::new();
+        for (&index, d) in self.doors.iter_mut() {
+            *d = match *d {
+                // If the door is closed.. keep it closed!
+                DoorState::Closed => DoorState::Closed,
+                // If the door is closing, make it close more until done
+                DoorState::Closing(a) => {
+                    match a - deltatime > 0.0 {
+                        true  => DoorState::Closing(a - deltatime),
+                        false => DoorState::Closed,
                     }
-
-                    // Check and add all neighbours of this cell
-                    let neighbour_offsets = [index.checked_add(1), index.checked_sub(1), index.checked_add(self.width), index.checked_sub(self.width)];
-                    for (i, neighbour_index) in neighbour_offsets.iter().enumerate() {
-                        // Skip if the neighbour isn't valid
-                        if neighbour_index.is_none() { continue; }
-                        // Make sure we don't go off an edge
-                        if match i {
-                            /* Right */ 0 => { index % self.width == self.width-1 }
-                            /* Left  */ 1 => { index % self.width == 0 }
-                            /* Down  */ 2 => { index / self.width == self.height-1 }
-                            /* Up    */ _ => { index / self.width == 0 }
-                        } { continue; }
-                        // Skip if the neighbour is solid
-                        if self.cells.get(neighbour_index.unwrap()).unwrap().kind == 1 { continue; }
-                        neighbours.push(neighbour_index.unwrap());
-                    } 
                 }
-                // Remove all duplicate neighbours
-                neighbours.sort_unstable();
-                neighbours.dedup();
-                // Make it so the new frontier is made up of the neighbours we just found
-                fronteir = std::mem::replace(&mut neighbours, fronteir);
-                // Clear the neighbours for next run
-                neighbours.clear();
-
-                light_level -= 1;
-            }
+                // If the door is opening, make it open more until done
+                DoorState::Opening(a) => {
+                    match a - deltatime > 0.0 {
+                        true  => DoorState::Opening(a - deltatime),
+                        false => {
+                            // update collision map to remove the door here
+                            changed_doors.push(index);
+                            DoorState::Open(5.0)
+                        },
+                    }
+                }
+                // If the door is open, wait 5 seconds and then close it
+                DoorState::Open(a) => {
+                    match a - deltatime > 0.0 {
+                        true  => DoorState::Open(a - deltatime),
+                        false => {
+                            // update collision map to add the door back, you should only be able to walk through if it's OPEN
+                            changed_doors.push(index);
+                            DoorState::Closing(0.5)
+                        },
+                    }
+                }
+            };
+        }
+        self.update_door_shapes();
+        for index in changed_doors {
+            self.update_lightmap_around(index);
         }
     }
 
+    // Works out the shapes again for any doors that have moved since last time
+    pub fn update_door_shapes(&mut self) {
+        for (&index, door) in &self.doors {
+            let open = door.open_amount();
+            if self.door_shapes.get(&index).is_some_and(|&(built_for, _)| built_for == open) { continue; }
+            let cell = &self.cells[index];
+            self.door_shapes.insert(index, (open, self.shapes.get_open(cell.kind, cell.flags, open)));
+        }
+    }
+
+    fn calculate_solid_neighbours(&mut self) {
+        self.solid_neighbours = (0..self.cells.len()).map(|index| {
+            let (x, y) = self.index_to_coord(index);
+            NEIGHBOURS.iter().enumerate().fold(0, |solid, (bit, &(dx, dy))| {
+                let (Some(nx), Some(ny)) = (x.checked_add_signed(dx), y.checked_add_signed(dy)) else { return solid };
+                match nx < self.width && ny < self.height && self.get(self.coord_to_index(&nx, &ny)).blocks_light() {
+                    true  => solid | 1 << bit,
+                    false => solid,
+                }
+            })
+        }).collect();
+    }
+
     fn calculate_collision(&mut self) {
         self.collision = vec![
-            [point![5.0, 3.0], point![5.0, 2.0]], 
-            [point![5.0, 2.0], point![5.0, 1.0]], 
-            [point![5.0, 1.0], point![4.0, 1.0]], 
-            [point![4.0, 1.0], point![3.0, 1.0]], 
-            [point![3.0, 1.0], point![2.0, 1.0]], 
-            [point![2.0, 1.0], point![1.0, 2.0]], 
-            [point![1.0, 2.0], point![1.0, 3.0]], 
-            [point![1.0, 3.0], point![1.0, 4.0]], 
-            [point![1.0, 4.0], point![2.0, 5.0]], 
-            [point![2.0, 5.0], point![3.0, 6.0]], 
-            [point![3.0, 6.0], point![4.0, 6.0]], 
-            [point![4.0, 6.0], point![4.0, 7.0]], 
-            [point![5.0, 7.0], point![5.0, 6.0]], 
-            [point![5.0, 6.0], point![6.0, 6.0]], 
-            [point![2.0, 2.0], point![2.0, 2.0]], 
-            [point![3.25, 2.25], point![3.25, 2.75]], 
-            [point![3.75, 2.25], point![3.75, 2.75]], 
-            [point![3.25, 2.25], point![3.75, 2.25]], 
-            [point![3.25, 2.75], point![3.75, 2.75]], 
-        ]
+            Segm::Line(5.0, 3.0, 5.0, 2.0),
+            Segm::Line(5.0, 2.0, 5.0, 1.0),
+            Segm::Line(5.0, 1.0, 4.0, 1.0),
+            Segm::Line(4.0, 1.0, 3.0, 1.0),
+            Segm::Line(3.0, 1.0, 2.0, 1.0),
+            Segm::Line(2.0, 1.0, 1.0, 2.0),
+            Segm::Line(1.0, 2.0, 1.0, 3.0),
+            Segm::Line(1.0, 3.0, 1.0, 4.0),
+            Segm::Line(1.0, 4.0, 2.0, 5.0),
+            Segm::Line(2.0, 5.0, 3.0, 6.0),
+            Segm::Line(3.0, 6.0, 4.0, 6.0),
+            Segm::Line(4.0, 6.0, 4.0, 7.0),
+            Segm::Line(5.0, 7.0, 5.0, 6.0),
+            Segm::Line(5.0, 6.0, 6.0, 6.0),
+            Segm::Line(2.0, 2.0, 2.0, 2.0),
+            Segm::Line(3.25, 2.25, 3.25, 2.75),
+            Segm::Line(3.75, 2.25, 3.75, 2.75),
+            Segm::Line(3.25, 2.25, 3.75, 2.25),
+            Segm::Line(3.25, 2.75, 3.75, 2.75),
+        ];
+        // Anything round is solid, e.g. round pillars
+        for (index, cell) in self.cells.iter().enumerate() {
+            let (x, y) = self.index_to_coord(index);
+            for &shape in self.shapes.get(cell.kind, cell.flags) {
+                if let Shape::Circle(cx, cy, radius) = shape {
+                    self.collision.push(Segm::Circle(x as f64 + cx, y as f64 + cy, radius));
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,293 @@
+// Baking the lightmap!
+// Light spreads out from every light a cell at a time, getting one level dimmer each step, and each cell keeps the brightest light that reaches it.
+// Rather than flood filling from each light one after the other, every light goes at once, brightest first,
+// so each cell only ever gets visited once per channel (per side it's come in from) however many lights there are.
+
+use std::path::Path;
+
+use image::{Rgb, RgbImage};
+use nalgebra::Vector2;
+
+use crate::game::{lights::{DynamicLight, LightAnimation}, settings::LightmapMode};
+use crate::util::shape::shape_hit;
+
+use super::{DoorState, Map};
+
+// How bright a light is at its brightest
+pub const MAX_LIGHT: u8 = 16;
+// So how many cells away a light can reach
+const RANGE: usize = MAX_LIGHT as usize - 1;
+
+// Light passing through a cell, the side it came in from and the side it can leave from are stored as one bit each out of a u16
+// Sides are right, left, down, up (so the opposite side is always 'side ^ 1'), NO_SIDE is for the light itself
+const NO_SIDE: usize = 4;
+const ALL_PASSAGES: u16 = u16::MAX;
+const SAME_SIDE_PASSAGES: u16 = 0b_1000_0100_0010_0001;
+
+fn passage_bit(from: usize, to: usize) -> u16 {
+    1 << (from * 4 + to)
+}
+
+// A point on one of the sides of a cell, 'along' goes from 0 to 1
+fn side_point(side: usize, along: f64) -> Vector2<f64> {
+    match side {
+        /* Right */ 0 => Vector2::new(1.0, along),
+        /* Left  */ 1 => Vector2::new(0.0, along),
+        /* Down  */ 2 => Vector2::new(along, 1.0),
+        /* Up    */ _ => Vector2::new(along, 0.0),
+    }
+}
+
+// Works out how bright each channel of a light starts off from its flags (0b_RR_GG_BB_AA), 3 is as bright as a light can be (16).
+// AA is whether it flickers or anything, see LightAnimation
+pub fn light_colour(flags: u8) -> [u8; 3] {
+    [6, 4, 2].map(|shift| ((flags >> shift) & 0b11) * MAX_LIGHT / 3)
+}
+
+// Light levels go from 0 to 16 but images go from 0 to 255
+fn level_to_byte(level: u8) -> u8 {
+    ((level as u16 * 255 + MAX_LIGHT as u16 / 2) / MAX_LIGHT as u16) as u8
+}
+
+fn byte_to_level(byte: u8) -> u8 {
+    ((byte as u16 * MAX_LIGHT as u16 + 127) / 255) as u8
+}
+
+// One bit for each number up to a length, for keeping track of what we've already done without a big Vec<bool>
+struct BitSet {
+    bits: Vec<u64>,
+}
+
+impl BitSet {
+    fn new(len: usize) -> BitSet {
+        BitSet { bits: vec![0; len.div_ceil(64)] }
+    }
+
+    // Returns false if it was already in there
+    fn insert(&mut self, i: usize) -> bool {
+        let (word, bit) = (i / 64, 1 << (i % 64));
+        let new = self.bits[word] & bit == 0;
+        self.bits[word] |= bit;
+        new
+    }
+}
+
+// A rectangle of cells on the map, 'max' isn't included
+#[derive(Clone, Copy)]
+struct Area {
+    min: (usize, usize),
+    max: (usize, usize),
+}
+
+impl Area {
+    fn width (&self) -> usize { self.max.0 - self.min.0 }
+    fn height(&self) -> usize { self.max.1 - self.min.1 }
+
+    fn contains(&self, (x, y): (usize, usize)) -> bool {
+        x >= self.min.0 && x < self.max.0 && y >= self.min.1 && y < self.max.1
+    }
+
+    // Index of a cell in a Vec covering just this area
+    fn local(&self, (x, y): (usize, usize)) -> usize {
+        (y - self.min.1) * self.width() + (x - self.min.0)
+    }
+}
+
+impl Map {
+    pub fn calculate_lightmap(&mut self) {
+        self.light_passages = (0..self.cells.len()).map(|index| self.cell_light_passages(index)).collect();
+        self.lightmap = vec![[0; 3]; self.width*self.height];
+        self.baked_lightmap = vec![[0; 3]; self.width*self.height];
+
+        let whole_map = Area { min: (0, 0), max: (self.width, self.height) };
+        self.spread_light(whole_map, whole_map, whole_map);
+    }
+
+    // Redoes the light around a cell that's changed (like a door opening or closing) without going over the whole map
+    pub fn update_lightmap_around(&mut self, index: usize) {
+        self.light_passages[index] = self.cell_light_passages(index);
+
+        // Only lights within RANGE of the cell could have had their light changed, and those can only light up cells within RANGE of themselves.
+        // Those cells could be lit by anything within RANGE of them though, and that light could have come from anywhere within RANGE of its light
+        let pos = self.index_to_coord(index);
+        let changed = self.area_around(pos, RANGE * 2);
+        let lights  = self.area_around(pos, RANGE * 3);
+        let spread  = self.area_around(pos, RANGE * 4);
+        self.spread_light(spread, lights, changed);
+    }
+
+    fn area_around(&self, (x, y): (usize, usize), radius: usize) -> Area {
+        Area {
+            min: (x.saturating_sub(radius), y.saturating_sub(radius)),
+            max: ((x + radius + 1).min(self.width), (y + radius + 1).min(self.height)),
+        }
+    }
+
+    // Spreads light from every light inside 'lights' through 'spread', then saves the light for the cells inside 'changed'
+    fn spread_light(&mut self, spread: Area, lights: Area, changed: Area) {
+        // Lights that change over time get added every frame instead, see update_dynamic_light()
+        let sources: Vec<(usize, [u8; 3])> = (lights.min.1..lights.max.1)
+            .flat_map(|y| (lights.min.0..lights.max.0).map(move |x| (x, y)))
+            .map(|(x, y)| self.coord_to_index(&x, &y))
+            .filter(|&index| self.cells[index].kind == 2 && LightAnimation::from_flags(self.cells[index].flags) == LightAnimation::Steady)
+            .map(|index| (index, light_colour(self.cells[index].flags)))
+            .collect();
+        let light = self.spread_from(spread, &sources);
+
+        for y in changed.min.1..changed.max.1 {
+            for x in changed.min.0..changed.max.0 {
+                let index = self.coord_to_index(&x, &y);
+                self.baked_lightmap[index] = light[spread.local((x, y))];
+                self.lightmap[index] = self.with_painted_light(index, self.baked_lightmap[index]);
+            }
+        }
+    }
+
+    // Spreads light from each source (a cell and how bright each channel is there) through 'spread', and gives back how bright every cell in it ends up
+    fn spread_from(&self, spread: Area, sources: &[(usize, [u8; 3])]) -> Vec<[u8; 3]> {
+        let width = spread.width();
+        let mut light = vec![[0u8; 3]; width * spread.height()];
+
+        for channel in 0..3 {
+            // Cells (and the side the light came in from, as 'cell * 5 + side') waiting to be lit, for each light level
+            let mut levels: Vec<Vec<usize>> = vec![Vec::new(); MAX_LIGHT as usize + 1];
+            for &(index, colour) in sources {
+                let level = colour[channel].min(MAX_LIGHT);
+                levels[level as usize].push(spread.local(self.index_to_coord(index)) * 5 + NO_SIDE);
+            }
+            let mut done = BitSet::new(light.len() * 5);
+
+            // Brightest first, so the first time we get to a cell (from a side) is as bright as it can be from there
+            for level in (1..=MAX_LIGHT).rev() {
+                let fronteir = std::mem::take(&mut levels[level as usize]);
+                for state in fronteir {
+                    if !done.insert(state) { continue; }
+                    let (cell, entry) = (state / 5, state % 5);
+                    light[cell][channel] = light[cell][channel].max(level);
+                    if level == 1 { continue; }
+
+                    let (x, y) = (cell % width + spread.min.0, cell / width + spread.min.1);
+                    let index = self.coord_to_index(&x, &y);
+                    let neighbours = [(x.wrapping_add(1), y), (x.wrapping_sub(1), y), (x, y.wrapping_add(1)), (x, y.wrapping_sub(1))];
+                    for (side, neighbour) in neighbours.into_iter().enumerate() {
+                        // Make sure we don't go off the edge
+                        if !spread.contains(neighbour) { continue; }
+                        // Skip if something in this cell stops the light getting from where it came in over to this side
+                        if entry != NO_SIDE && self.light_passages[index] & passage_bit(entry, side) == 0 { continue; }
+                        // Skip if the neighbour is solid (light can still get over walls that aren't full height)
+                        if self.get(self.coord_to_index(&neighbour.0, &neighbour.1)).blocks_light() { continue; }
+                        // The light goes into the neighbour from the opposite side
+                        levels[level as usize - 1].push(spread.local(neighbour) * 5 + (side ^ 1));
+                    }
+                }
+            }
+        }
+        light
+    }
+
+    // Works out the light from everything that moves or changes for this frame, on top of the baked lightmap.
+    // Each light only spreads out as far as it can reach, so this is quick enough to do every frame
+    pub fn update_dynamic_light<'a>(&mut self, lights: impl IntoIterator<Item = &'a DynamicLight>) {
+        self.dynamic_lightmap.clear();
+        self.dynamic_lightmap.resize(self.width*self.height, [0; 3]);
+
+        for light in lights {
+            if light.pos.x < 0.0 || light.pos.y < 0.0 || light.pos.x >= self.width as f64 || light.pos.y >= self.height as f64 { continue; }
+            let pos = (light.pos.x as usize, light.pos.y as usize);
+            let index = self.coord_to_index(&pos.0, &pos.1);
+            if self.get(index).blocks_light() { continue; }
+
+            let colour = light.brightness().map(|c| c.round().clamp(0.0, MAX_LIGHT as f64) as u8);
+            let spread = self.area_around(pos, RANGE);
+            let spread_light = self.spread_from(spread, &[(index, colour)]);
+
+            for y in spread.min.1..spread.max.1 {
+                for x in spread.min.0..spread.max.0 {
+                    let amount = light.cone_amount(Vector2::new(x as f64 + 0.5, y as f64 + 0.5));
+                    if amount <= 0.0 { continue; }
+                    let cell = self.coord_to_index(&x, &y);
+                    // Lights add together (unlike in the baked lightmap) so a torch still shows up in a room that's already lit
+                    for (d, &l) in self.dynamic_lightmap[cell].iter_mut().zip(&spread_light[spread.local((x, y))]) {
+                        *d = (*d + (l as f64 * amount).round() as u8).min(MAX_LIGHT);
+                    }
+                }
+            }
+        }
+    }
+
+    // How bright a cell is this frame, the baked light plus any dynamic lights
+    pub fn light(&self, index: usize) -> [u8; 3] {
+        let mut light = self.lightmap[index];
+        if let Some(dynamic) = self.dynamic_lightmap.get(index) {
+            for (l, &d) in light.iter_mut().zip(dynamic) {
+                *l = (*l + d).min(MAX_LIGHT);
+            }
+        }
+        light
+    }
+
+    // Mixes the hand painted light for a cell (if there is any) in with the light we've worked out
+    fn with_painted_light(&self, index: usize, light: [u8; 3]) -> [u8; 3] {
+        let Some(&painted) = self.painted_lightmap.get(index) else { return light };
+        match self.settings.lightmap_mode {
+            LightmapMode::Replace => painted,
+            LightmapMode::Add => [0, 1, 2].map(|i| (light[i] + painted[i]).min(MAX_LIGHT)),
+        }
+    }
+
+    // Loads the lightmap image from the map's settings, if it has one. It's next to the map image and the same size as it
+    pub(super) fn load_painted_lightmap(&mut self, map_path: &str) {
+        let Some(file) = &self.settings.lightmap else { return };
+        let path = Path::new(map_path).with_file_name(file);
+        let img = match image::open(&path) {
+            Ok(img) => img.to_rgb8(),
+            Err(err) => { eprintln!("couldn't load lightmap {}: {err}", path.display()); return; }
+        };
+        if (img.width() as usize, img.height() as usize) != (self.width, self.height) {
+            eprintln!("lightmap {} is {}x{} but the map is {}x{}", path.display(), img.width(), img.height(), self.width, self.height);
+            return;
+        }
+        self.painted_lightmap = img.pixels().map(|p| p.0.map(byte_to_level)).collect();
+    }
+
+    // Saves the lightmap as an image (one pixel per cell) so it can be touched up and loaded back in with the 'lightmap' setting.
+    // It's only the light from the map's own lights, any painted light isn't in it (or it'd get added on twice)
+    pub fn save_lightmap(&self, path: &str) -> image::ImageResult<()> {
+        let img = RgbImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            Rgb(self.baked_lightmap[self.coord_to_index(&(x as usize), &(y as usize))].map(level_to_byte))
+        });
+        img.save(path)
+    }
+
+    // Works out which sides of a cell light can get between (see passage_bit), using the actual shape in the cell.
+    fn cell_light_passages(&self, index: usize) -> u16 {
+        let cell = &self.cells[index];
+        match cell.kind {
+            // Nothing in the way
+            0..=2 => ALL_PASSAGES,
+            // Doors only let light through once they're all the way open
+            3 => match self.doors.get(&index) {
+                Some(DoorState::Open(..)) => ALL_PASSAGES,
+                _ => SAME_SIDE_PASSAGES,
+            },
+            // Everything else, check if there's a clear line from one side to the other
+            _ => {
+                let (x, y) = self.index_to_coord(index);
+                let mut passages = SAME_SIDE_PASSAGES;
+                for from in 0..4 {
+                    for to in (0..4).filter(|&to| to != from) {
+                        // A few lines between points on each side, slightly different points on each end so none of them are perfectly straight
+                        let clear = [0.2, 0.8].iter().any(|&a| [0.25, 0.75].iter().any(|&b| {
+                            let start = side_point(from, a) + Vector2::new(x as f64, y as f64);
+                            let end   = side_point(to,   b) + Vector2::new(x as f64, y as f64);
+                            let length = (end - start).magnitude();
+                            shape_hit(self, index, Vector2::new(x, y), start, end - start).iter().all(|hit| hit.0 >= length)
+                        }));
+                        if clear { passages |= passage_bit(from, to); }
+                    }
+                }
+                passages
+            }
+        }
+    }
+}
@@ -2,6 +2,9 @@ pub mod player;
 pub mod map;
 pub mod texture;
 pub mod collision;
+pub mod sprite;
+pub mod settings;
+pub mod lights;
 
 use nalgebra::Vector2;
 
@@ -10,26 +13,92 @@ use map::Map;
 
 use image;
 
-use self::{map::Cell, texture::Texture};
+use self::{lights::{DynamicLight, LightAnimation}, map::{light_colour, MAX_LIGHT}, sprite::Sprite, texture::Texture};
 
 pub struct Game {
     pub player: Player,
     pub map: Map,
     pub textures: Vec<Texture>,
+    pub sprites: Vec<Sprite>,
+    // Lights that can be added, moved and removed whenever, on top of the ones on the map
+    pub lights: Vec<DynamicLight>,
+    // Seconds since the game started, for flickering lights
+    pub time: f64,
 }
 
+// Index of the lamp sprite in Game::textures
+pub const LAMP_TEXTURE_INDEX: usize = 7;
+
 impl Game {
     pub fn new() -> Game {
-        Game {
+        Game::from_map("res/images/map3.png").unwrap()
+    }
+
+    // Fails if the map image can't be opened
+    pub fn from_map(map_path: &str) -> image::ImageResult<Game> {
+        let map = Map::load(String::from(map_path))?;
+
+        // Put a lamp under every light
+        let sprites = map.cells.iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.kind == 2)
+            .map(|(index, _)| {
+                let (x, y) = map.index_to_coord(index);
+                Sprite::new(Vector2::new(x as f64 + 0.5, y as f64 + 0.5), 0.7, LAMP_TEXTURE_INDEX)
+            })
+            .collect();
+
+        Ok(Game {
             // TODO: Make player position load from map
             player: Player::new(Vector2::new(13.0, 4.0)),
-            map: Map::load(String::from("res/images/map3.png")),
+            map,
             textures: vec![
                 Texture::from_file("res/images/wall1.png"),
                 Texture::from_file("res/images/door2.png"),
                 Texture::from_file("res/images/elevator.png"),
                 Texture::from_file("res/images/map3.png"),
+                // Floors and ceilings
+                Texture::from_file("res/images/weasel/floor.png"),
+                Texture::from_file("res/images/stone.png"),
+                Texture::from_file("res/images/metal.png"),
+                // Sprites
+                Texture::from_file("res/images/sprites/lamp.png").with_colour_key(),
+                // See-through walls
+                Texture::from_file("res/images/grate.png").with_colour_key(),
+                // Mostly see-through, apart from the bright bits
+                Texture::from_file("res/images/weasel/holo.png").with_alpha(|[r, _, _]| if r > 150 { 235 } else { 70 }),
             ],
+            sprites,
+            lights: Vec::new(),
+            time: 0.0,
+        })
+    }
+
+    // Moves time on for the dynamic lights and works out how they light up the map this frame
+    pub fn update_lights(&mut self, deltatime: f64) {
+        self.time += deltatime;
+        for light in self.lights.iter_mut() {
+            if let Some((left, _)) = &mut light.lifetime { *left -= deltatime; }
+        }
+        // is_none_or would be neater but needs Rust 1.82
+        #[allow(clippy::unnecessary_map_or)]
+        self.lights.retain(|light| light.lifetime.map_or(true, |(left, _)| left > 0.0));
+
+        // Lights on the map that flicker or strobe
+        let mut lights: Vec<DynamicLight> = self.map.cells.iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.kind == 2 && LightAnimation::from_flags(cell.flags) != LightAnimation::Steady)
+            .map(|(index, cell)| {
+                let (x, y) = self.map.index_to_coord(index);
+                let brightness = LightAnimation::from_flags(cell.flags).brightness(self.time, index);
+                let colour = light_colour(cell.flags).map(|c| c as f64 * brightness);
+                DynamicLight::point(Vector2::new(x as f64 + 0.5, y as f64 + 0.5), colour)
+            })
+            .collect();
+        if self.player.flashlight {
+            let max = MAX_LIGHT as f64;
+            lights.push(DynamicLight::spotlight(self.player.pos, self.player.dir, 0.35, [max - 2.0, max - 2.0, max - 4.0]));
         }
+        self.map.update_dynamic_light(lights.iter().chain(&self.lights));
     }
 }
\ No newline at end of file
@@ -13,7 +13,7 @@ pub struct Player {
     pub dir: Vector2<f64>,
     pub pitch: f64,
     pub head_bob_amount: f64,
-    pub cam_plane: Vector2<f64>,
+    pub flashlight: bool,
 
     pub mid_ray_dist: f64,
 }
@@ -22,10 +22,15 @@ impl Player {
     pub fn new(pos: Vector2<f64>) -> Player {
         Player {
             pos, dir: Vector2::new(1.0, 0.0), pitch: 0.0,
-            vel: Vector2::zeros(), cam_plane: Vector2::new(-1.0, 0.0), head_bob_amount: 0.0, mid_ray_dist: 0.0,
+            vel: Vector2::zeros(), head_bob_amount: 0.0, flashlight: false, mid_ray_dist: 0.0,
         }
     }
 
+    // The camera plane, perpendicular to where we're looking. The wider it is the bigger the fov
+    pub fn cam_plane(&self, fov: f64) -> Vector2<f64> {
+        Vector2::new(-self.dir.y, self.dir.x) * fov
+    }
+
     pub fn step(&mut self, map: &Map, dir: Vector2<f64>, delta: f64) {
         // TODO: better movement
         let dir = dir * 0.6;
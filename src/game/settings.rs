@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use nalgebra::Vector2;
+
+// Extra settings for a map that don't fit in the map image, loaded from a text file with the same name next to it (e.g. map4.png -> map4.txt)
+// Each line looks like 'fog_density = 0.1', lines starting with '#' are comments
+pub struct MapSettings {
+    // What colour things fade into in the distance
+    pub fog_colour: [u8; 3],
+    // How quickly things fade into the fog, 0 for no fog at all
+    pub fog_density: f64,
+    // A hand painted lightmap image (greyscale or colour, one pixel per cell) next to the map, e.g. 'lightmap = map4_light.png'
+    pub lightmap: Option<String>,
+    pub lightmap_mode: LightmapMode,
+    // Which way the light shines across the whole map (e.g. 'light_direction = 1, 2' comes from the top left), for shading walls by which way they face
+    pub light_direction: Vector2<f64>,
+    // How much darker walls facing away from the light are, from 0 (not at all) to 1 (black)
+    pub shading: f64,
+}
+
+// What to do with a hand painted lightmap
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LightmapMode {
+    // Add it on top of the light from the lights on the map
+    Add,
+    // Use it instead, the lights on the map only light things up if they're painted on
+    Replace,
+}
+
+impl Default for MapSettings {
+    fn default() -> Self {
+        MapSettings { fog_colour: [0, 0, 0], fog_density: 0.0, lightmap: None, lightmap_mode: LightmapMode::Add,
+            light_direction: Vector2::new(1.0, 2.0).normalize(), shading: 0.5 }
+    }
+}
+
+impl MapSettings {
+    // Loads the settings for the map at 'map_path', if there's no settings file it just uses the defaults
+    pub fn load(map_path: &str) -> MapSettings {
+        let mut settings = MapSettings::default();
+        let Ok(file) = std::fs::read_to_string(Path::new(map_path).with_extension("txt")) else { return settings };
+
+        for (line_number, line) in file.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+
+            let parsed = match line.split_once('=') {
+                Some((key, value)) => settings.set(key.trim(), value.trim()),
+                None => Err(String::from("expected 'key = value'")),
+            };
+            if let Err(err) = parsed {
+                eprintln!("{map_path} settings, line {}: {err}", line_number + 1);
+            }
+        }
+        settings
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "fog_colour" | "fog_color" => self.fog_colour = parse_colour(value)?,
+            "fog_density" => self.fog_density = value.parse().map_err(|_| format!("'{value}' isn't a number"))?,
+            "light_direction" => {
+                let [x, y] = parse_numbers(value)?;
+                let direction = Vector2::new(x, y);
+                if direction.magnitude() == 0.0 { return Err(String::from("light_direction can't be 0, 0")); }
+                self.light_direction = direction.normalize();
+            }
+            "shading" => self.shading = value.parse::<f64>().map_err(|_| format!("'{value}' isn't a number"))?.clamp(0.0, 1.0),
+            "lightmap" => self.lightmap = Some(String::from(value)),
+            "lightmap_mode" => self.lightmap_mode = match value {
+                "add" => LightmapMode::Add,
+                "replace" => LightmapMode::Replace,
+                _ => return Err(format!("'{value}' should be 'add' or 'replace'")),
+            },
+            _ => return Err(format!("unknown setting '{key}'")),
+        }
+        Ok(())
+    }
+}
+
+// Parses "r, g, b"
+fn parse_colour(value: &str) -> Result<[u8; 3], String> {
+    let channels: Vec<u8> = value.split(',')
+        .map(|c| c.trim().parse().map_err(|_| format!("'{c}' isn't a colour channel (0 to 255)")))
+        .collect::<Result<_, _>>()?;
+    channels.try_into().map_err(|_| format!("'{value}' should look like r, g, b"))
+}
+
+// Parses "a, b, ..."
+fn parse_numbers<const N: usize>(value: &str) -> Result<[f64; N], String> {
+    let numbers: Vec<f64> = value.split(',')
+        .map(|n| n.trim().parse().map_err(|_| format!("'{n}' isn't a number")))
+        .collect::<Result<_, _>>()?;
+    numbers.try_into().map_err(|_| format!("'{value}' should be {N} numbers separated by commas"))
+}
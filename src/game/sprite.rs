@@ -0,0 +1,15 @@
+use nalgebra::Vector2;
+
+// A billboard in the world (items, lamps, enemies, etc), always drawn facing the camera.
+// Stands on the floor and is 'size' units tall (and wide), a size of 1.0 is the same height as a wall.
+pub struct Sprite {
+    pub pos: Vector2<f64>,
+    pub size: f64,
+    pub texture_index: usize,
+}
+
+impl Sprite {
+    pub fn new(pos: Vector2<f64>, size: f64, texture_index: usize) -> Sprite {
+        Sprite { pos, size, texture_index }
+    }
+}
@@ -1,37 +1,136 @@
+use std::path::Path;
+
 use image;
 
-// Stores a texture as a vector of RGB values in column order for quick access!!!
+// Stores a texture as a vector of RGBA values in column order for quick access!!!
+
+// Old textures without an alpha channel use this colour for see-through pixels (for sprites, and walls you can see through like grates),
+// same magenta as the player spawn in the map images. Textures loaded with with_colour_key() have them turned into fully transparent pixels.
+pub const TRANSPARENT_COLOUR: [u8; 3] = [255, 0, 255];
+
+// How a texture lets what's behind it show through
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Transparency {
+    // Every pixel is solid
+    Opaque,
+    // Pixels are either solid or not there at all (grates, fences, sprites)
+    CutOut,
+    // Some pixels are partly see-through and get mixed with whatever's behind (holograms, glass)
+    Blended,
+}
+
+impl Transparency {
+    // Works it out from RGBA pixels
+    fn of(data: &[u8]) -> Transparency {
+        let has_holes   = data.chunks_exact(4).any(|pixel| pixel[3] == 0);
+        let has_partial = data.chunks_exact(4).any(|pixel| pixel[3] != 0 && pixel[3] != 255);
+        match (has_holes, has_partial) {
+            (_, true)      => Transparency::Blended,
+            (true, false)  => Transparency::CutOut,
+            (false, false) => Transparency::Opaque,
+        }
+    }
+}
 
 pub struct Texture {
     pub data: Vec<u8>,
     pub width : usize,
     pub height: usize,
+    pub transparency: Transparency,
+    // Which pixels glow, so they're drawn at full brightness whatever the light and fog, in the same order as 'data'.
+    // Comes from an image next to the texture with '_emissive' on the end of the name (white glows, black doesn't), empty if there isn't one
+    pub emissive: Vec<bool>,
 }
 
-// This file has the number '3' in it an awful lot, that's because textures are stored as RGB, (3 elements per pixel).
+// This file has the number '4' in it an awful lot, that's because textures are stored as RGBA, (4 elements per pixel).
 impl Texture {
     pub fn from_file(path: &str) -> Texture {
-        let image = image::open(path).unwrap().to_rgb8();
+        let image = image::open(path).unwrap().to_rgba8();
         let width : usize = image.width() .try_into().unwrap();
         let height: usize = image.height().try_into().unwrap();
         let image_data = image.into_raw();
+        let transparency = Transparency::of(&image_data);
 
         // Rotates the image so it's stored as a 1 dimensional array of columns, rather than rows. This lets us access it way faster!
         let mut rotated_image = vec![0; image_data.len()];
 
-        for (i, pixel) in image_data.chunks_exact(3).enumerate() {
+        for (i, pixel) in image_data.chunks_exact(4).enumerate() {
             let column = i % width;
             let row    = i / width;
 
-            let index = (column * height + row)*3;
-            rotated_image[index..index+3].clone_from_slice(pixel);
+            let index = (column * height + row)*4;
+            rotated_image[index..index+4].clone_from_slice(pixel);
         }
 
-        Texture { data: rotated_image, width, height }
+        let emissive = Texture::load_emissive_mask(path, width, height);
+        Texture { data: rotated_image, width, height, transparency, emissive }
+    }
+
+    // Cuts out every TRANSPARENT_COLOUR pixel, for sprites and see-through walls that don't have an alpha channel.
+    // Only for textures that are meant to have holes, anything else could have that colour in it on purpose (like the map image)
+    pub fn with_colour_key(mut self) -> Texture {
+        for pixel in self.data.chunks_exact_mut(4) {
+            if pixel[0..3] == TRANSPARENT_COLOUR { pixel[3] = 0; }
+        }
+        self.transparency = Transparency::of(&self.data);
+        self
+    }
+
+    // Makes every pixel that's there partly see-through, 'alpha' picks how much from the pixel's colour.
+    // Lets a normal opaque image be used for something like a hologram without having to change the image
+    pub fn with_alpha(mut self, alpha: impl Fn([u8; 3]) -> u8) -> Texture {
+        for pixel in self.data.chunks_exact_mut(4).filter(|pixel| pixel[3] != 0) {
+            pixel[3] = alpha([pixel[0], pixel[1], pixel[2]]);
+        }
+        self.transparency = Transparency::Blended;
+        self
+    }
+
+    // Loads e.g. 'lamp_emissive.png' for 'lamp.png', rotated into columns like the texture
+    fn load_emissive_mask(path: &str, width: usize, height: usize) -> Vec<bool> {
+        let path = Path::new(path);
+        let Some(stem) = path.file_stem() else { return Vec::new() };
+        let mask_path = path.with_file_name(format!("{}_emissive.png", stem.to_string_lossy()));
+        let Ok(mask) = image::open(&mask_path) else { return Vec::new() };
+        let mask = mask.to_luma8();
+        if (mask.width() as usize, mask.height() as usize) != (width, height) {
+            eprintln!("{} isn't the same size as its texture", mask_path.display());
+            return Vec::new();
+        }
+
+        let mut emissive = vec![false; width*height];
+        for (i, pixel) in mask.pixels().enumerate() {
+            emissive[(i % width) * height + i / width] = pixel.0[0] >= 128;
+        }
+        emissive
+    }
+
+    // If a pixel should ignore light and fog
+    pub fn is_emissive(&self, x: usize, y: usize) -> bool {
+        self.emissive.get(x * self.height + y).copied().unwrap_or(false)
+    }
+
+    // If rays should keep going past walls with this texture
+    pub fn see_through(&self) -> bool {
+        self.transparency != Transparency::Opaque
+    }
+
+    // Returns the RGBA values of a single pixel, used for floors and ceilings where we can't go a column at a time
+    pub fn get_pixel(&self, x: usize, y: usize) -> &[u8] {
+        let index = (x * self.height + y)*4;
+        &self.data[index..index+4]
     }
 
     // TODO:
     // pub fn get_slice(usize: column) ->  {
 
     // }
-}
\ No newline at end of file
+}
+
+// Mixes 'src' over 'dst' using the alpha of 'src', for drawing see-through pixels over whatever's behind them
+pub fn blend(dst: &mut [u8], src: &[u8]) {
+    let alpha = u16::from(src[3]);
+    for (d, &s) in dst[0..3].iter_mut().zip(&src[0..3]) {
+        *d = ((u16::from(s) * alpha + u16::from(*d) * (255 - alpha)) / 255) as u8;
+    }
+}
@@ -0,0 +1,6 @@
+extern crate nalgebra as na;
+use na::Vector2;
+
+pub mod renderer;
+pub mod util;
+pub mod game;
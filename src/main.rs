@@ -1,6 +1,6 @@
 use std::{ops::Rem, time::Instant};
 
-use game::{map::{Cell, DoorState}, Game};
+use raycaster::{game::{lights::DynamicLight, map::DoorState, Game}, renderer::{self, RenderConfig}, util};
 use winit::{
     dpi::{LogicalPosition, LogicalSize, PhysicalPosition}, event::{Event, WindowEvent}, event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget}, keyboard::KeyCode, window::{Fullscreen, WindowBuilder, WindowButtons}// platform::windows::WindowBuilderExtWindows,
 };
@@ -13,28 +13,23 @@ use pixels::{Error, Pixels, SurfaceTexture};
 extern crate nalgebra as na;
 use na::{point, vector, Vector2};
 
-pub mod renderer;
-pub mod util;
-pub mod game;
-
-const WIDTH : u32 = 480;
-const HEIGHT: u32 = WIDTH/2;//324;
-
-const WIDTH_USIZE: usize = WIDTH as usize;
-const HEIGHT_USIZE: usize = HEIGHT as usize;
-
-const ASPECT_RATIO: f64 = WIDTH as f64 / HEIGHT as f64;
-
 #[derive(Debug, PartialEq)]
 enum CursorMode {Free, Locked}
 
+// Aspect ratios you can cycle through with F3
+const ASPECT_RATIOS: [f64; 4] = [2.0, 16.0 / 9.0, 4.0 / 3.0, 1.0];
+// Biggest internal resolution '=' goes up to if we can't tell how big the screen is
+const MAX_RESOLUTION: (usize, usize) = (3840, 2160);
+
 fn main() {
+    let mut config = parse_args();
+
     let event_loop = EventLoop::new().unwrap();
     let mut input = WinitInputHelper::new();
 
     let window = {
-        let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
-        let scaled_size = LogicalSize::new(WIDTH as f64 * 2.0, HEIGHT as f64 * 2.0);
+        let size = LogicalSize::new(config.width as f64, config.height as f64);
+        let scaled_size = LogicalSize::new((config.width * config.scale as usize) as f64, (config.height * config.scale as usize) as f64);
         WindowBuilder::new()
             .with_title("Raycasting :3")
             // .with_title("Raycasting")
@@ -48,7 +43,7 @@ fn main() {
     let mut pixels = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(WIDTH, HEIGHT, surface_texture).unwrap()
+        Pixels::new(config.width as u32, config.height as u32, surface_texture).unwrap()
     };
 
     let mut deltatime: f64 = 0.0;
@@ -61,9 +56,10 @@ fn main() {
         if let Event::WindowEvent { event, .. } = &event {
             match event {
                 WindowEvent::RedrawRequested => {
-                    renderer::render_view(pixels.frame_mut(), &mut g, 2.0);
+                    g.player.mid_ray_dist = renderer::render_view(pixels.frame_mut(), &g, &config).mid_ray_dist;
                     if render_map {
-                        renderer::render_map(pixels.frame_mut(), &mut g, 15);
+                        let cell_size = config.map_cell_size(&g);
+                        renderer::render_map(pixels.frame_mut(), &g, &config, cell_size);
                     }
 
                     if let Err(err) = pixels.render() {
@@ -116,6 +112,40 @@ fn main() {
                 window.set_cursor_position(LogicalPosition::new(window.inner_size().width/2, window.inner_size().height/2));
             }
 
+            // Changing the internal resolution, aspect ratio and window scale
+            let mut new_config = config;
+            // Never more pixels than the screen has (or it'd just eat memory), and never less than 1
+            let max_size = window.current_monitor().map_or(MAX_RESOLUTION, |monitor| (monitor.size().width as usize, monitor.size().height as usize));
+            if input.key_pressed(KeyCode::Minus) && config.width >= 2 && config.height >= 2 {
+                new_config = config.with_size(config.width / 2, config.height / 2, config.scale * 2);
+            }
+            if input.key_pressed(KeyCode::Equal) && config.width * 2 <= max_size.0 && config.height * 2 <= max_size.1 {
+                new_config = config.with_size(config.width * 2, config.height * 2, (config.scale / 2).max(1));
+            }
+            if input.key_pressed(KeyCode::BracketLeft)  { new_config.scale = (config.scale - 1).max(1); }
+            if input.key_pressed(KeyCode::BracketRight) { new_config.scale = config.scale + 1; }
+            if input.key_pressed(KeyCode::KeyL) { new_config.smooth_lighting = !config.smooth_lighting; }
+            if input.key_pressed(KeyCode::KeyO) { new_config.ambient_occlusion = !config.ambient_occlusion; }
+            if input.key_pressed(KeyCode::F3) {
+                // Move on to the next aspect ratio after the closest one to what we've got now
+                let current = ASPECT_RATIOS.iter().enumerate()
+                    .min_by(|a, b| (a.1 - config.aspect_ratio()).abs().total_cmp(&(b.1 - config.aspect_ratio()).abs()))
+                    .map(|(i, _)| i).unwrap_or(0);
+                new_config = config.with_aspect_ratio(ASPECT_RATIOS[(current + 1) % ASPECT_RATIOS.len()]);
+            }
+            if new_config != config {
+                if new_config.width != config.width || new_config.height != config.height {
+                    if let Err(err) = pixels.resize_buffer(new_config.width as u32, new_config.height as u32) {
+                        return log_error("pixels.resize_buffer", err, control_flow);
+                    }
+                    window.set_min_inner_size(Some(LogicalSize::new(new_config.width as f64, new_config.height as f64)));
+                }
+                if new_config.scale != config.scale && window.fullscreen().is_none() {
+                    let _ = window.request_inner_size(LogicalSize::new((new_config.width * new_config.scale as usize) as f64, (new_config.height * new_config.scale as usize) as f64));
+                }
+                config = new_config;
+            }
+
             if input.key_pressed(KeyCode::F11) {
                 match window.fullscreen() {
                     None => { window.set_fullscreen(Some(Fullscreen::Borderless(None))); }
@@ -124,39 +154,7 @@ fn main() {
             }
 
             // Updating doors
-            for d in g.map.doors.values_mut() {
-                *d = match *d {
-                    // If the door is closed.. keep it closed!
-                    DoorState::Closed => DoorState::Closed,
-                    // If the door is closing, make it close more until done
-                    DoorState::Closing(a) => {
-                        match a - deltatime > 0.0 {
-                            true  => DoorState::Closing(a - deltatime),
-                            false => DoorState::Closed,
-                        }
-                    }
-                    // If the door is opening, make it open more until done
-                    DoorState::Opening(a) => {
-                        match a - deltatime > 0.0 {
-                            true  => DoorState::Opening(a - deltatime),
-                            false => {
-                                // update collision map to remove the door here
-                                DoorState::Open(5.0)
-                            },
-                        }
-                    }
-                    // If the door is open, wait 5 seconds and then close it
-                    DoorState::Open(a) => {
-                        match a - deltatime > 0.0 {
-                            true  => DoorState::Open(a - deltatime),
-                            false => {
-                                // update collision map to add the door back, you should only be able to walk through if it's OPEN
-                                DoorState::Closing(0.5)
-                            },
-                        }
-                    }
-                };
-            }
+            g.map.update_doors(deltatime);
 
             // Opening doors
             if input.key_pressed(KeyCode::KeyE) {
@@ -178,6 +176,12 @@ fn main() {
 
             // Player controls            
             if input.key_pressed(KeyCode::KeyC) { render_map = !render_map; }
+            if input.key_pressed(KeyCode::KeyF) { g.player.flashlight = !g.player.flashlight; }
+            // Bang! (there's nothing to shoot yet but the flash looks cool)
+            if input.key_pressed(KeyCode::KeyQ) {
+                let pos = g.player.pos + g.player.dir * 0.3;
+                g.lights.push(DynamicLight::burst(pos, [16.0, 13.0, 8.0], 0.15));
+            }
             // if input.key_held(KeyCode::KeyQ) { fov = (fov - deltatime).max(0.01); }
             // if input.key_held(KeyCode::KeyE) { fov += deltatime; }
 
@@ -204,15 +208,41 @@ fn main() {
                 if input.key_held(KeyCode::ArrowDown) { g.player.pitch += 5.0; }
                 // println!("{:?}", g.player.pitch);
 
-                g.player.pitch = (g.player.pitch + input.mouse_diff().1 as f64 / 2.0).clamp(-121.0, 121.0);
+                let max_pitch = (config.height / 2) as f64 + 1.0;
+                g.player.pitch = (g.player.pitch + input.mouse_diff().1 as f64 / 2.0).clamp(-max_pitch, max_pitch);
             }
 
+            // Flickering lights, the flashlight and anything else that moves
+            g.update_lights(deltatime);
+
             // Redraw
             window.request_redraw();
         }
     }).unwrap();
 }
 
+// Works out the starting RenderConfig from the command line
+// e.g. raycaster --size 640x320 --scale 2 --fov 2.0
+fn parse_args() -> RenderConfig {
+    let mut config = RenderConfig::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next();
+        let parsed = match (arg.as_str(), value.as_deref()) {
+            ("--size", Some(v)) => RenderConfig::parse_size(v).map(|(w, h)| config = config.with_size(w, h, config.scale)),
+            ("--scale", Some(v)) => v.parse().ok().map(|s| config.scale = s),
+            ("--fov", Some(v)) => v.parse().ok().map(|f| config.fov = f),
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("raycaster: bad argument '{arg}'");
+            eprintln!("usage: raycaster [--size <w>x<h>] [--scale <n>] [--fov <fov>]");
+            std::process::exit(1);
+        }
+    }
+    config.with_size(config.width, config.height, config.scale)
+}
+
 fn log_error<E: std::error::Error + 'static>(method_name: &str, err: E, control_flow: &EventLoopWindowTarget<()>) {
     error!("{method_name}() failed: {err}");
     for source in err.sources().skip(1) {
@@ -1,151 +1,641 @@
-use crate::{WIDTH, HEIGHT, WIDTH_USIZE, HEIGHT_USIZE, na, Vector2, util, Game};
+use crate::{na, Vector2, util::{self, RaycastHit}, game::Game};
+use crate::game::{collision::Segm, map::MAX_LIGHT, settings::MapSettings, sprite::Sprite, texture::{self, Texture}};
 
+use image::RgbaImage;
 use na::vector;
+use rayon::prelude::*;
 use pixels_primitives;
 // use rand::{thread_rng, Rng};
 
 // const GRID_SIZE: u32 = 12;
 // const GRID_SIZE_F64: f64 = GRID_SIZE as f64; // TODO: find out if i need this 
 
-pub fn render_view(screen: &mut [u8], game: &mut Game, fov: f64) {
-    game.player.cam_plane = Vector2::new(-game.player.dir.y, game.player.dir.x) * fov;
-
-    // floor and ceiling
-    let middle = (((HEIGHT/2) as f64 - game.player.pitch) as usize).min(HEIGHT as usize-1);
-
-    // let light_level = game.map.lightmap[game.map.coord_to_index(&(game.player.pos.x as usize), &(game.player.pos.y as usize))];
-    let light_level = 15;
-    let floor_col = [
-        (26 / 16) * (light_level + 1),
-        (28 / 16) * (light_level + 1),
-        (44 / 16) * (light_level + 1),
-    255];
-    let ceil_col = [
-        (51 / 16) * (light_level + 1),
-        (60 / 16) * (light_level + 1),
-        (87 / 16) * (light_level + 1),
-    255];
-
-    draw_rect(screen, 0, 0,      WIDTH as usize, middle,            &floor_col);
-    draw_rect(screen, 0, middle, WIDTH as usize, HEIGHT as usize,   &ceil_col);
-    
-    // TODO: make it so no-matter the aspect ratio, the map is always cubes
-    // for i in 0..100 {
-    for w in 0..WIDTH {
-        // if w % 3 != 0 {continue;}
-
-        let ray_direction = game.player.dir + (game.player.cam_plane * (w as f64 / WIDTH as f64 * 2.0 - 1.0));
-        let raycast_result = util::raycast(game, game.player.pos, ray_direction, 500.0);
-        if let Some((cell, distance, texture_along, brightness, side)) = raycast_result {
-            // Calculating heights
-            let head_height = (game.player.head_bob_amount.sin() / distance) * 10.0;
-
-            // let h = HEIGHT as f64;
-            // let lineheight = (h / distance);// * (1.0/ASPECT_RATIO);
-            // let mut draw_start = -lineheight / 2.0 + h / 2.0 + head_height - game.player.pitch;
-            // if draw_start < 0.0 { draw_start = 0.0 };
-            // let mut draw_end = lineheight / 2.0 + h / 2.0 + head_height - game.player.pitch;
-            // if draw_end > h { draw_end = h };
-
-            // TODO: Make this better
-            let h = HEIGHT as isize;
-            let lineheight = (h as f64 / (distance*fov)) as isize;// * (1.0/ASPECT_RATIO);
-            let line_start = -lineheight / 2 + h / 2 + (head_height - game.player.pitch) as isize;
-            // if draw_start < 0 { draw_start = 0 };
-            let line_end   =  lineheight / 2 + h / 2 + (head_height - game.player.pitch) as isize;
-            // if draw_end > h { draw_end = h };
-
-            
-
-            // Color stuff
-            // let mut color = get_col(game.map[cell]-1);
-            let mut color = [255; 4];
-
-            color[0] = (color[0] as f32 * (brightness as f32 / 255.0)) as u8;
-            color[1] = (color[1] as f32 * (brightness as f32 / 255.0)) as u8;
-            color[2] = (color[2] as f32 * (brightness as f32 / 255.0)) as u8;
-
-            let offset = match game.map.get(cell).kind == 1 {
-                true => {
-                    if side == 0 {
-                        match ray_direction.x.is_sign_positive() {
-                            true  => -1,
-                            false =>  1,
-                        }
-                    } else {
-                        match ray_direction.y.is_sign_positive() {
-                            true  => -(game.map.width as isize),
-                            false =>   game.map.width as isize,
-                        }
+// Everything about the frame we're drawing into.
+// The internal resolution can be anything, the window just scales it up by 'scale'.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderConfig {
+    pub width : usize,
+    pub height: usize,
+    pub scale: u32,
+    pub fov: f64,
+    // Draw bands of columns on all cores at once, looks exactly the same as drawing them one at a time
+    pub parallel: bool,
+    // Blend the light between cells, rather than each cell having one flat light level (which looks more retro)
+    pub smooth_lighting: bool,
+    // Darken inside corners and where the floor meets the walls
+    pub ambient_occlusion: bool,
+}
+
+impl Default for RenderConfig {
+    fn default() -> RenderConfig {
+        RenderConfig { width: 480, height: 240, scale: 2, fov: 2.0, parallel: true, smooth_lighting: true, ambient_occlusion: true }
+    }
+}
+
+impl RenderConfig {
+    pub fn new(width: usize, height: usize, scale: u32, fov: f64) -> RenderConfig {
+        RenderConfig { fov, ..RenderConfig::default() }.with_size(width, height, scale)
+    }
+
+    // Same settings at a different resolution
+    pub fn with_size(self, width: usize, height: usize, scale: u32) -> RenderConfig {
+        RenderConfig { width: width.max(1), height: height.max(1), scale: scale.max(1), ..self }
+    }
+
+    pub fn aspect_ratio(&self) -> f64 {
+        self.width as f64 / self.height as f64
+    }
+
+    // Keeps the width the same and works out the height from an aspect ratio
+    pub fn with_aspect_ratio(self, aspect_ratio: f64) -> RenderConfig {
+        RenderConfig { height: ((self.width as f64 / aspect_ratio).round() as usize).max(1), ..self }
+    }
+
+    // How many bytes a frame buffer needs to be
+    pub fn buffer_len(&self) -> usize {
+        self.width * self.height * 4
+    }
+
+    // How tall (in pixels) a wall 1 unit away is.
+    // Worked out from the width so the map is always cubes, no matter the aspect ratio
+    pub fn wall_height(&self) -> f64 {
+        self.width as f64 / (2.0 * self.fov)
+    }
+
+    // How far the head bob moves things 1 unit away (10 pixels at the default resolution)
+    pub fn head_bob(&self, head_bob_amount: f64) -> f64 {
+        head_bob_amount.sin() * self.wall_height() / 12.0
+    }
+
+    // Biggest cell size that fits the whole map on screen when drawing it with render_map()
+    pub fn map_cell_size(&self, game: &Game) -> usize {
+        (self.width / game.map.width).min(self.height / game.map.height).max(1)
+    }
+
+    // Parses a size like "480x240"
+    pub fn parse_size(s: &str) -> Option<(usize, usize)> {
+        let (w, h) = s.split_once('x')?;
+        Some((w.trim().parse().ok()?, h.trim().parse().ok()?))
+    }
+}
+
+// Anything render_view() works out that the rest of the game might want to know
+pub struct FrameInfo {
+    // How far away the wall in the middle of the screen is
+    pub mid_ray_dist: f64,
+}
+
+// Stuff that's the same for every column of a frame, worked out once before the columns get split up between threads
+struct View<'a> {
+    game: &'a Game,
+    config: &'a RenderConfig,
+    cam_plane: Vector2<f64>,
+    head_height: f64,
+    // How high the player's eyes are, in blocks
+    eye_height: f64,
+    // How far away the floor/ceiling is for each row of the screen (and if it's the floor)
+    row_distances: Vec<(f64, bool)>,
+    // The rays on the very left and very right of the screen, each row of the floor/ceiling steps from one to the other
+    ray_left: Vector2<f64>,
+    ray_right: Vector2<f64>,
+    // How foggy each row of the floor/ceiling is, so we don't have to work it out for every pixel
+    row_fog: Vec<Fog>,
+    // Sprites that are in front of the camera, furthest first
+    sprites: Vec<ProjectedSprite<'a>>,
+}
+
+// Where a sprite ends up on screen
+struct ProjectedSprite<'a> {
+    sprite: &'a Sprite,
+    depth: f64,
+    left: f64,
+    top: f64,
+    bottom: f64,
+    size: f64,
+}
+
+// How much something gets blended into the map's fog colour, worked out from how far away it is
+#[derive(Clone, Copy)]
+struct Fog {
+    colour: [u8; 3],
+    // Out of 256
+    amount: u16,
+}
+
+impl Fog {
+    fn at(settings: &MapSettings, distance: f64) -> Fog {
+        let amount = match settings.fog_density > 0.0 {
+            // Exponential fog, so it gets thicker quickly up close then slowly fades everything out
+            true  => ((1.0 - (-settings.fog_density * distance).exp()) * 256.0).round() as u16,
+            false => 0,
+        };
+        Fog { colour: settings.fog_colour, amount: amount.min(256) }
+    }
+
+    fn apply(&self, pixel: &mut [u8]) {
+        if self.amount == 0 { return; }
+        for (p, &f) in pixel.iter_mut().zip(&self.colour) {
+            *p = ((u16::from(*p) * (256 - self.amount) + u16::from(f) * self.amount) / 256) as u8;
+        }
+    }
+}
+
+// Ambient occlusion, how much darker it gets right in a corner and how far out from the wall it goes
+const AO_STRENGTH: f64 = 0.45;
+const AO_RADIUS: f64 = 0.5;
+
+// How much light gets to a point in a cell (from 0 to 1 across it) with solid blocks next to it, 1 is all of it.
+// 'solid' is the cell's bits from Map::solid_neighbours, any sides in 'skip' don't count (for the wall we're drawing on)
+fn occlusion(solid: u8, pos: Vector2<f64>, skip: u8) -> f64 {
+    let solid = solid & !skip;
+    if solid == 0 { return 1.0; }
+    // How far away the point is from each neighbour, in the same order as NEIGHBOURS
+    let (right, left, down, up) = (1.0 - pos.x, pos.x, 1.0 - pos.y, pos.y);
+    let distances = [
+        right, left, down, up,
+        right.hypot(down), left.hypot(down), right.hypot(up), left.hypot(up),
+    ];
+    // Getting closer to more walls makes it darker, but never more than AO_STRENGTH
+    let mut amount: f64 = 0.0;
+    for (bit, d) in distances.into_iter().enumerate() {
+        if solid & 1 << bit == 0 { continue; }
+        let closeness = (1.0 - d / AO_RADIUS).max(0.0).powi(2);
+        amount += closeness - amount * closeness;
+    }
+    1.0 - AO_STRENGTH * amount
+}
+
+// How bright a wall facing 'normal' is, from 1 facing straight into the map's light down to (1 - shading) facing directly away from it
+fn face_brightness(settings: &MapSettings, normal: Vector2<f64>) -> f64 {
+    let facing = normal.dot(&-settings.light_direction);
+    1.0 - settings.shading * (1.0 - facing) / 2.0
+}
+
+pub fn render_view(screen: &mut [u8], game: &Game, config: &RenderConfig) -> FrameInfo {
+    let view = View::new(game, config);
+
+    // Each column of the frame is independent, so we draw into a buffer stored as columns rather than rows,
+    // which lets it get chopped up into bands of columns that can all be drawn at the same time.
+    let column_len = config.height * 4;
+
+    // The floor and ceiling get drawn a row at a time though, straight on to the screen, then copied into the columns
+    let draw_row = |(h, row): (usize, &mut [u8])| view.draw_floor_and_ceiling(row, h);
+    match config.parallel {
+        true  => screen.par_chunks_mut(config.width * 4).enumerate().for_each(draw_row),
+        false => screen.chunks_mut(config.width * 4).enumerate().for_each(draw_row),
+    }
+    let mut columns = vec![0; config.buffer_len()];
+    let transpose_column = |(w, column): (usize, &mut [u8])| {
+        for (h, pixel) in column.chunks_exact_mut(4).enumerate() {
+            let pos = (w + h * config.width) * 4;
+            pixel.copy_from_slice(&screen[pos..pos+4]);
+        }
+    };
+    match config.parallel {
+        true  => columns.par_chunks_mut(column_len).enumerate().for_each(transpose_column),
+        false => columns.chunks_mut(column_len).enumerate().for_each(transpose_column),
+    }
+
+
+    // Split it up into a few bands per thread so one slow band (e.g. lots of sprites) doesn't hold everything up
+    let bands = match config.parallel {
+        true  => rayon::current_num_threads() * 4,
+        false => 1,
+    };
+    let band_width = config.width.div_ceil(bands).max(1);
+    let draw_band = |(band, band_columns): (usize, &mut [u8])| view.draw_band(band * band_width, band_columns);
+    // Only the band with the middle column in it gives back how far away its wall is
+    let mid_ray_dist = match config.parallel {
+        true  => columns.par_chunks_mut(band_width * column_len).enumerate().filter_map(draw_band).reduce_with(f64::min),
+        false => columns.chunks_mut(band_width * column_len).enumerate().filter_map(draw_band).reduce(f64::min),
+    };
+
+    // Turn the columns back into rows for the screen
+    let transpose_row = |(h, row): (usize, &mut [u8])| {
+        for (w, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let pos = w * column_len + h * 4;
+            pixel.copy_from_slice(&columns[pos..pos+4]);
+        }
+    };
+    match config.parallel {
+        true  => screen.par_chunks_mut(config.width * 4).enumerate().for_each(transpose_row),
+        false => screen.chunks_mut(config.width * 4).enumerate().for_each(transpose_row),
+    }
+
+    // Draw 'crosshair'
+    let (mid_w, mid_h) = (config.width / 2, config.height / 2);
+    draw_rect(screen, config, mid_w.saturating_sub(2), mid_h.saturating_sub(2), mid_w + 2, mid_h + 2, &[0xFF, 0xAA, 0x00, 0xFF]);
+
+    FrameInfo { mid_ray_dist: mid_ray_dist.unwrap_or(0.0) }
+}
+
+impl<'a> View<'a> {
+    fn new(game: &'a Game, config: &'a RenderConfig) -> View<'a> {
+        let cam_plane = game.player.cam_plane(config.fov);
+        let horizon = (config.height/2) as f64 - game.player.pitch;
+        let head_height = config.head_bob(game.player.head_bob_amount);
+
+        // Uses the same maths as the walls (so pitch and head bob line up), just solved for the distance instead.
+        let half_wall = config.wall_height() / 2.0;
+        let row_distances = (0..config.height).map(|h| {
+            // Use the middle of the pixel so we never divide by zero on the horizon
+            let y = h as f64 + 0.5;
+            match y > horizon {
+                true  => ((half_wall + head_height) / (y - horizon), true),
+                false => ((half_wall - head_height) / (horizon - y), false),
+            }
+        }).collect::<Vec<_>>();
+
+        let row_fog = row_distances.iter().map(|&(distance, _)| Fog::at(&game.map.settings, distance)).collect();
+
+        // Work out where each sprite is relative to the camera, 'depth' is how far in front and 'across' is how far to the side (as a multiple of the cam plane)
+        let dir = game.player.dir;
+        let inv_det = 1.0 / (dir.x * cam_plane.y - cam_plane.x * dir.y);
+        let mut sprites: Vec<ProjectedSprite> = game.sprites.iter()
+            .filter_map(|sprite| {
+                let relative = sprite.pos - game.player.pos;
+                let depth  = (relative.x * cam_plane.y - cam_plane.x * relative.y) * inv_det;
+                let across = (dir.x * relative.y - relative.x * dir.y) * inv_det;
+                // Don't bother with anything behind us (or practically inside us)
+                if depth <= 0.05 { return None; }
+
+                let screen_x = (config.width as f64 / 2.0) * (1.0 + across / depth);
+                // Sprites are as wide as they are tall
+                let size = sprite.size * config.wall_height() / depth;
+                // Sprites stand on the floor
+                let bottom = horizon + (half_wall + head_height) / depth;
+                Some(ProjectedSprite { sprite, depth, left: screen_x - size / 2.0, top: bottom - size, bottom, size })
+            })
+            .collect();
+        sprites.sort_unstable_by(|a, b| b.depth.total_cmp(&a.depth));
+
+        View { game, config, cam_plane, head_height, eye_height: 0.5 + head_height / config.wall_height(), row_distances, ray_left: game.player.dir - cam_plane, ray_right: game.player.dir + cam_plane, row_fog, sprites }
+    }
+
+    // Draws a band of columns starting at 'first_column', 'columns' holds each column one after the other.
+    // Gives back how far away the nearest wall in the middle of the screen is, if it's in this band and there is one
+    fn draw_band(&self, first_column: usize, columns: &mut [u8]) -> Option<f64> {
+        let game = self.game;
+        let config = self.config;
+
+        let mut mid_ray_dist = None;
+        for (i, column) in columns.chunks_exact_mut(config.height * 4).enumerate() {
+            let w = first_column + i;
+            let ray_direction = game.player.dir + (self.cam_plane * (w as f64 / config.width as f64 * 2.0 - 1.0));
+
+            // Everything the ray hit, up to the first wall we can't see through
+            let hits = util::raycast_all(game, game.player.pos, ray_direction, 500.0, self.eye_height);
+            if w == config.width / 2 {
+                mid_ray_dist = hits.first().map(|hit| hit.1);
+            }
+            // Anything past a wall we can't see through is hidden
+            let hidden_past = match hits.last() {
+                Some(hit) if util::hides_everything_behind(game, hit.0) => hit.1,
+                _ => f64::INFINITY,
+            };
+
+            // Draw the walls and sprites from back to front, so see-through walls show what's behind them
+            let mut sprites = self.sprites.iter().filter(|s| s.depth < hidden_past).peekable();
+            for &hit in hits.iter().rev() {
+                while let Some(projected) = sprites.next_if(|s| s.depth >= hit.1) {
+                    self.draw_sprite(column, w, projected);
+                }
+                self.draw_wall(column, ray_direction, hit);
+            }
+            for projected in sprites {
+                self.draw_sprite(column, w, projected);
+            }
+        }
+        mid_ray_dist
+    }
+
+    // How bright it is at a point on the map. With smooth lighting it's blended between the middles of the four closest cells,
+    // otherwise (or if they're all solid) it's just the light in 'cell'
+    fn light_at(&self, pos: Vector2<f64>, cell: usize) -> [f64; 3] {
+        let map = &self.game.map;
+        let flat = map.light(cell).map(f64::from);
+        if !self.config.smooth_lighting { return flat; }
+
+        let (x, y) = (pos.x - 0.5, pos.y - 0.5);
+        let (left, top) = (x.floor(), y.floor());
+        let (tx, ty) = (x - left, y - top);
+        let corners = [(0, 0, (1.0 - tx) * (1.0 - ty)), (1, 0, tx * (1.0 - ty)), (0, 1, (1.0 - tx) * ty), (1, 1, tx * ty)];
+
+        let mut light = [0.0; 3];
+        let mut total = 0.0;
+        for (dx, dy, weight) in corners {
+            let (cx, cy) = (left as isize + dx, top as isize + dy);
+            if cx < 0 || cy < 0 || cx >= map.width as isize || cy >= map.height as isize { continue; }
+            let index = map.coord_to_index(&(cx as usize), &(cy as usize));
+            // Solid blocks never have any light in them, so leave them out rather than making everything next to them darker
+            if map.get(index).blocks_light() { continue; }
+            for (l, c) in light.iter_mut().zip(map.light(index)) {
+                *l += f64::from(c) * weight;
+            }
+            total += weight;
+        }
+        match total > 0.0 {
+            true  => light.map(|l| l / total),
+            false => flat,
+        }
+    }
+
+    fn draw_wall(&self, column: &mut [u8], ray_direction: Vector2<f64>, (cell, distance, texture_along, normal, side): RaycastHit) {
+        let game = self.game;
+        let config = self.config;
+
+        // Calculating heights
+        let head_height = self.head_height / distance;
+
+        // let h = HEIGHT as f64;
+        // let lineheight = (h / distance);// * (1.0/ASPECT_RATIO);
+        // let mut draw_start = -lineheight / 2.0 + h / 2.0 + head_height - game.player.pitch;
+        // if draw_start < 0.0 { draw_start = 0.0 };
+        // let mut draw_end = lineheight / 2.0 + h / 2.0 + head_height - game.player.pitch;
+        // if draw_end > h { draw_end = h };
+
+        // TODO: Make this better
+        let h = config.height as isize;
+        let lineheight = (config.wall_height() / distance) as isize;
+        let line_start = -lineheight / 2 + h / 2 + (head_height - game.player.pitch) as isize;
+        // if draw_start < 0 { draw_start = 0 };
+        let line_end   =  lineheight / 2 + h / 2 + (head_height - game.player.pitch) as isize;
+        // if draw_end > h { draw_end = h };
+        // Walls all stand on the floor, so taller or shorter ones just stretch the top up or down
+        let wall_height = game.map.get(cell).wall_height();
+        let line_start = line_end - ((line_end - line_start) as f64 * wall_height).round() as isize;
+
+        // Color stuff
+        // let mut color = get_col(game.map[cell]-1);
+        let mut color = [255; 4];
+
+        let brightness = face_brightness(&game.map.settings, normal);
+        color[0] = (color[0] as f64 * brightness) as u8;
+        color[1] = (color[1] as f64 * brightness) as u8;
+        color[2] = (color[2] as f64 * brightness) as u8;
+
+        let offset = match game.map.get(cell).kind == 1 {
+            true => {
+                if side == 0 {
+                    match ray_direction.x.is_sign_positive() {
+                        true  => -1,
+                        false =>  1,
+                    }
+                } else {
+                    match ray_direction.y.is_sign_positive() {
+                        true  => -(game.map.width as isize),
+                        false =>   game.map.width as isize,
                     }
                 }
-                _ => 0
+            }
+            _ => 0
+        };
+        let front = cell.saturating_add_signed(offset);
+        // Solid blocks take their light from the cell in front of the face, so sample along the middle of that cell
+        // to blend between it and its neighbours along the wall. Everything else is lit right where the ray hit it
+        let hit_pos = game.player.pos + ray_direction * distance;
+        let light_pos = match (offset, side) {
+            (0, _) => hit_pos,
+            (_, 0) => Vector2::new(game.map.index_to_coord(front).0 as f64 + 0.5, hit_pos.y),
+            _      => Vector2::new(hit_pos.x, game.map.index_to_coord(front).1 as f64 + 0.5),
+        };
+        let light_level = self.light_at(light_pos, front);
+        // Darken the ends of the wall that are tucked into a corner, only the blocks either side along the wall count
+        if config.ambient_occlusion && offset != 0 {
+            let (fx, fy) = game.map.index_to_coord(front);
+            let skip = match side {
+                0 => 0b1111_0011,
+                _ => 0b1111_1100,
             };
-            let light_level = game.map.lightmap[cell.saturating_add_signed(offset)];
-            color[0] = (color[0] / 16) * (light_level + 1);
-            color[1] = (color[1] / 16) * (light_level + 1);
-            color[2] = (color[2] / 16) * (light_level + 1);
+            let ao = occlusion(game.map.solid_neighbours[front], hit_pos - Vector2::new(fx as f64, fy as f64), skip);
+            for c in color[0..3].iter_mut() {
+                *c = (*c as f64 * ao) as u8;
+            }
+        }
+        for (c, l) in color[0..3].iter_mut().zip(light_level) {
+            *c = ((*c / 16) as f64 * (l + 1.0)) as u8;
+        }
+
+        let tex = &game.textures[game.map.cells[cell].texture_index as usize];
+        // If we can see the top of a solid block, draw that first
+        if game.map.get(cell).kind == 1 {
+            self.draw_wall_top(column, ray_direction, cell, front, distance, tex);
+        }
+        draw_slice(column, tex, texture_along, (line_start, line_end), wall_height, &color, Fog::at(&game.map.settings, distance));
+    }
 
-            // draw_line(screen, Vector2::new(w as f64, draw_start), Vector2::new(w as f64, draw_end), &color);
-            draw_slice(screen, game, w as usize, texture_along, line_start, line_end, &color, game.map.cells[cell].texture_index.into());
+    // Draws the top of a solid block that's shorter than the player's eyes, from where the ray enters the block to where it leaves it
+    // 'front' is the cell the ray came from, which the top gets its light from when lighting isn't smooth
+    fn draw_wall_top(&self, column: &mut [u8], ray_direction: Vector2<f64>, cell: usize, front: usize, distance: f64, tex: &Texture) {
+        let game = self.game;
+        let config = self.config;
+        let horizon = (config.height/2) as f64 - game.player.pitch;
 
-            if w == WIDTH / 2 { game.player.mid_ray_dist = distance }
+        // How far below the eyes the top is (in pixels at a distance of 1), if it's not below them we can't see it
+        let drop = config.wall_height() / 2.0 + self.head_height - game.map.get(cell).wall_height() * config.wall_height();
+        if drop <= 0.0 { return; }
+
+        // Where the ray leaves the block
+        let (x, y) = game.map.index_to_coord(cell);
+        let exit = |pos: f64, dir: f64, min: usize| match dir {
+            d if d > 0.0 => (min as f64 + 1.0 - pos) / d,
+            d if d < 0.0 => (min as f64 - pos) / d,
+            _ => f64::INFINITY,
+        };
+        let exit_distance = exit(game.player.pos.x, ray_direction.x, x).min(exit(game.player.pos.y, ray_direction.y, y));
+
+        let draw_start = ((horizon + drop / exit_distance) as isize).clamp(0, config.height as isize) as usize;
+        let draw_end   = ((horizon + drop / distance     ) as isize).clamp(0, config.height as isize) as usize;
+        for h in draw_start..draw_end {
+            let world_pos = game.player.pos + ray_direction * (drop / (h as f64 + 0.5 - horizon));
+            let tex_x = ((world_pos.x.fract() * tex.width  as f64) as usize).min(tex.width  - 1);
+            let tex_y = ((world_pos.y.fract() * tex.height as f64) as usize).min(tex.height - 1);
+            let texel = tex.get_pixel(tex_x, tex_y);
+            if tex.is_emissive(tex_x, tex_y) {
+                column[h*4..h*4+3].copy_from_slice(&texel[0..3]);
+                continue;
+            }
+            for ((out, &t), l) in column[h*4..h*4+3].iter_mut().zip(texel).zip(self.light_at(world_pos, front)) {
+                *out = ((t / 16) as f64 * (l + 1.0)) as u8;
+            }
+            Fog::at(&game.map.settings, drop / (h as f64 + 0.5 - horizon)).apply(&mut column[h*4..h*4+3]);
+        }
+    }
+
+    // Works out where each pixel of the floor and ceiling in this column lands on the map and samples that cell's texture.
+    // Draws a row of the floor or ceiling, working out where each pixel lands on the map and sampling that cell's texture
+    fn draw_floor_and_ceiling(&self, row: &mut [u8], h: usize) {
+        let game = self.game;
+        // Used for anything that lands outside of the map. There's no light to look up out there, so they keep full brightness (only fog changes them)
+        let ceil_col  = [26, 28, 44];
+        let floor_col = [51, 60, 87];
+
+        let (row_distance, is_floor) = self.row_distances[h];
+        let fog = &self.row_fog[h];
+        // Where the leftmost pixel of this row is in the world, and how far to move for each pixel after that
+        let mut next_pos = game.player.pos + self.ray_left * row_distance;
+        let step = (self.ray_right - self.ray_left) * (row_distance / self.config.width as f64);
+
+        for out in row.chunks_exact_mut(4) {
+            let world_pos = next_pos;
+            next_pos += step;
+            let mut pixel = [0, 0, 0, 255];
+            let mut light = [MAX_LIGHT as f64; 3];
+            let mut ao = 1.0;
+
+            if world_pos.x >= 0.0 && world_pos.x < game.map.width as f64 && world_pos.y >= 0.0 && world_pos.y < game.map.height as f64 {
+                let index = game.map.coord_to_index(&(world_pos.x as usize), &(world_pos.y as usize));
+                let cell = game.map.get(index);
+                light = self.light_at(world_pos, index);
+                if self.config.ambient_occlusion {
+                    ao = occlusion(game.map.solid_neighbours[index], world_pos.map(f64::fract), 0);
+                }
+                let tex = &game.textures[match is_floor {
+                    true  => cell.floor_texture_index,
+                    false => cell.ceiling_texture_index,
+                } as usize];
+                let tex_x = (world_pos.x.fract() * tex.width  as f64) as usize;
+                let tex_y = (world_pos.y.fract() * tex.height as f64) as usize;
+                pixel[0..3].copy_from_slice(&tex.get_pixel(tex_x, tex_y)[0..3]);
+                if tex.is_emissive(tex_x, tex_y) {
+                    out.copy_from_slice(&pixel);
+                    continue;
+                }
+            } else {
+                pixel[0..3].copy_from_slice(match is_floor { true => &floor_col, false => &ceil_col });
+            }
+            // Lit the same as sprites, light levels go up to 16 so divide by 17
+            for (c, l) in pixel[0..3].iter_mut().zip(light) {
+                *c = (f64::from(*c) * (l + 1.0) * ao / 17.0) as u8;
+            }
+            fog.apply(&mut pixel);
+            out.copy_from_slice(&pixel);
+        }
+    }
+
+    // Draws the bit of a sprite that lands in this column
+    fn draw_sprite(&self, column: &mut [u8], w: usize, projected: &ProjectedSprite) {
+        let game = self.game;
+        let config = self.config;
+        let tex = &game.textures[projected.sprite.texture_index];
+
+        // The column it starts in counts even if it's only just in it
+        if (w as f64) < projected.left.floor() || (w as f64) >= (projected.left + projected.size).ceil() { return; }
+        let tex_column = ((w as f64 + 0.5 - projected.left) / projected.size * tex.width as f64) as usize;
+        if tex_column >= tex.width { return; }
+
+        // Sprites are lit the same as walls, using the light of whatever cell they're in.
+        // Outside the map they're at full brightness, same as the floor and ceiling out there
+        let pos = projected.sprite.pos;
+        let light_level = match pos.x >= 0.0 && pos.y >= 0.0 && pos.x < game.map.width as f64 && pos.y < game.map.height as f64 {
+            true  => self.light_at(pos, game.map.coord_to_index(&(pos.x as usize), &(pos.y as usize))),
+            false => [MAX_LIGHT as f64; 3],
+        };
+
+        let fog = Fog::at(&game.map.settings, projected.depth);
+
+        let draw_start = (projected.top.floor() as isize).clamp(0, config.height as isize) as usize;
+        let draw_end   = (projected.bottom.ceil() as isize).clamp(0, config.height as isize) as usize;
+        for y in draw_start..draw_end {
+            let row = ((y as f64 + 0.5 - projected.top) / projected.size * tex.height as f64) as usize;
+            if row >= tex.height { continue; }
+
+            let texel = tex.get_pixel(tex_column, row);
+            if texel[3] == 0 { continue; }
+
+            let mut pixel = [texel[0], texel[1], texel[2], texel[3]];
+            if !tex.is_emissive(tex_column, row) {
+                // Light levels go up to 16, so divide by 17 to keep it from overflowing
+                for (p, l) in pixel[0..3].iter_mut().zip(light_level) {
+                    *p = (f64::from(*p) * (l + 1.0) / 17.0) as u8;
+                }
+                fog.apply(&mut pixel);
+            }
+            texture::blend(&mut column[y*4..y*4+4], &pixel);
         }
     }
-    // Draw 'crosshair'
-    draw_rect(screen, (WIDTH/2 - 2) as usize, (HEIGHT/2 - 2) as usize, (WIDTH/2 + 2) as usize, (HEIGHT/2 + 2) as usize, &[0xFF, 0xAA, 0x00, 0xFF]);
+}
+
+// Renders a single frame from the given camera pose without needing a window, into an image we own (handy for saving to a PNG).
+pub fn render_offscreen(game: &mut Game, config: &RenderConfig, pos: Vector2<f64>, dir: Vector2<f64>, pitch: f64, show_map: bool) -> RgbaImage {
+    game.player.pos = pos;
+    game.player.dir = dir.normalize();
+    game.player.pitch = pitch;
+    // No head bob, so frames from the same pose always look the same
+    game.player.head_bob_amount = 0.0;
+    // Work out the dynamic lights without moving time on, so the same pose always looks the same
+    game.update_lights(0.0);
+
+    let mut screen = vec![0; config.buffer_len()];
+    game.player.mid_ray_dist = render_view(&mut screen, game, config).mid_ray_dist;
+    if show_map {
+        render_map(&mut screen, game, config, config.map_cell_size(game));
+    }
+    RgbaImage::from_raw(config.width as u32, config.height as u32, screen).unwrap()
 }
 
 // Draws a slice of a raycast
-fn draw_slice(screen: &mut [u8], game: &Game, screen_column: usize, along: f64, line_start: isize, line_end: isize, col: &[u8; 4], texture_index: usize) {
+// 'column' is the whole column of the screen we're drawing into, 'wall_height' is how many times the texture repeats up the slice
+fn draw_slice(column: &mut [u8], tex: &Texture, along: f64, (line_start, line_end): (isize, isize), wall_height: f64, col: &[u8; 4], fog: Fog) {
     // TODO: better way to do this
-    let draw_start = line_start.clamp(0, HEIGHT as isize) as usize;
-    let draw_end   = line_end  .clamp(0, HEIGHT as isize) as usize;
-
-    let tex = &game.textures[texture_index];
+    let screen_height = (column.len() / 4) as isize;
+    let draw_start = line_start.clamp(0, screen_height) as usize;
+    let draw_end   = line_end  .clamp(0, screen_height) as usize;
+    // Line the texture up with the floor, so a half height wall shows the bottom half of it
+    let wall_height = wall_height as f32;
+    let texture_offset = wall_height.ceil() - wall_height;
 
-    let column: usize = (along.rem_euclid(1.0) * (tex.width) as f64).floor() as usize; // TODO: Don't use 'as' here
+    let tex_column: usize = (along.rem_euclid(1.0) * (tex.width) as f64).floor() as usize; // TODO: Don't use 'as' here
 
-    let slice_begin = ( column    * tex.height) * 3;
-    let slice_end   = ((column+1) * tex.height) * 3;
+    let slice_begin = ( tex_column    * tex.height) * 4;
+    let slice_end   = ((tex_column+1) * tex.height) * 4;
     let column_slice = &tex.data[slice_begin..slice_end];
     // Make a vec out of it so we can recolour it
     let mut column_vec = Vec::from(column_slice);
-    // Recolour the whole column
-    for (i, p) in column_vec.iter_mut().enumerate() {
-        *p = ((u16::from(*p) * u16::from(col[i % 3])) / 255) as u8;
+    for (row, pixel) in column_vec.chunks_exact_mut(4).enumerate() {
+        // Glowing pixels stay as they are, apart from the alpha
+        let glows = tex.is_emissive(tex_column, row);
+        // Recolour the whole column (alpha too, so 'col' can make a wall more see-through)
+        for (i, p) in pixel.iter_mut().enumerate() {
+            if glows && i < 3 { continue; }
+            *p = ((u16::from(*p) * u16::from(col[i])) / 255) as u8;
+        }
+        if !glows { fog.apply(&mut pixel[0..3]); }
     }
 
     for h in draw_start..draw_end {
         // How far up the column we are
         // Not a fan of all the floats and casting, there's gotta be a way to do this with just ints, but oh well..!
         let ascent = (h as f32 - line_start as f32) / (line_end as f32 - line_start as f32);
+        // And how far up the texture, which repeats on walls taller than a block
+        let tex_ascent = (ascent * wall_height + texture_offset).fract();
 
-        let row = (ascent * tex.height as f32).floor() as usize*3;
-        let mut pixel = [0, 0, 0, 255];
-        pixel[0..3].copy_from_slice(&column_vec[row..row+3]);
-        pixel[3] = col[3];
-
-        let pos = 1*(screen_column)+WIDTH_USIZE * h;
-        screen[pos*4..pos*4+4].copy_from_slice(&pixel);
+        let row = (tex_ascent * tex.height as f32).floor() as usize*4;
+        let pixel = &column_vec[row..row+4];
+        match pixel[3] {
+            // Let whatever's behind show through
+            0   => continue,
+            255 => column[h*4..h*4+3].copy_from_slice(&pixel[0..3]),
+            _   => texture::blend(&mut column[h*4..h*4+4], pixel),
+        }
     }
 }
 
 // Draws the map on to the screen
-pub fn render_map(screen: &mut [u8], game: &Game, cell_size: usize) {
-    let render_offset_w = WIDTH_USIZE  / 2 - (game.map.width  * cell_size) / 2;
-    let render_offset_h = HEIGHT_USIZE / 2 - (game.map.height * cell_size) / 2;
-    let render_offset = Vector2::new(render_offset_w as f64, render_offset_h as f64);
+pub fn render_map(screen: &mut [u8], game: &Game, config: &RenderConfig, cell_size: usize) {
+    let render_offset_w = (config.width  / 2) as f64 - ((game.map.width  * cell_size) / 2) as f64;
+    let render_offset_h = (config.height / 2) as f64 - ((game.map.height * cell_size) / 2) as f64;
+    let render_offset = Vector2::new(render_offset_w, render_offset_h);
     // let map_size = Vector2::new((game.map.width * cell_size) as f64, (game.map.height * cell_size) as f64);
 
     for i in 0..game.map.width {
-        draw_line(screen,
+        draw_line(screen, config,
         Vector2::new((i*cell_size) as f64, 0.0) + render_offset,
         Vector2::new((i*cell_size) as f64, (game.map.height * cell_size) as f64) + render_offset,
         &[0x55, 0x55, 0x55, 0xFF]);
     }
     for i in 0..game.map.height {
-        draw_line(screen,
+        draw_line(screen, config,
         Vector2::new(0.0, (i*cell_size) as f64) + render_offset,
         Vector2::new((game.map.width * cell_size) as f64, (i*cell_size) as f64) + render_offset,
         &[0x55, 0x55, 0x55, 0xFF]);
@@ -159,40 +649,49 @@ pub fn render_map(screen: &mut [u8], game: &Game, cell_size: usize) {
     // }
     
 
-    pixels_primitives::circle_filled(screen, WIDTH as i32,
+    pixels_primitives::circle_filled(screen, config.width as i32,
         game.player.pos.x.clamp(0.0, game.map.width  as f64) * cell_size as f64 + render_offset.x,
         game.player.pos.y.clamp(0.0, game.map.height as f64) * cell_size as f64 + render_offset.y,
         crate::game::player::PLAYER_RADIUS * cell_size as f64, &[0x00, 0xFF, 0x00, 0xFF]);
-    draw_line(screen,
+    draw_line(screen, config,
          game.player.pos * cell_size as f64 + render_offset,
         (game.player.pos + game.player.dir * game.player.mid_ray_dist) * cell_size as f64 + render_offset,
         &[0xDD, 0xDD, 0xDD, 0xFF]);
 
     for seg in &game.map.collision {
-        draw_line(screen,
-            vector![seg[0].x, seg[0].y] * cell_size as f64 + render_offset,
-            vector![seg[1].x, seg[1].y] * cell_size as f64 + render_offset,
-            &[0xAA, 0xAA, 0xAA, 0xFF]);
-        for p in seg {
-            pixels_primitives::circle_filled(screen, WIDTH as i32,
-                p.x * cell_size as f64 + render_offset.x,
-                p.y * cell_size as f64 + render_offset.y,
-                cell_size as f64 / 6.0, &[0xFF, 0xFF, 0xFF, 0xFF]);
+        match *seg {
+            Segm::Line(x1, y1, x2, y2) => {
+                draw_line(screen, config,
+                    vector![x1, y1] * cell_size as f64 + render_offset,
+                    vector![x2, y2] * cell_size as f64 + render_offset,
+                    &[0xAA, 0xAA, 0xAA, 0xFF]);
+                for p in [vector![x1, y1], vector![x2, y2]] {
+                    pixels_primitives::circle_filled(screen, config.width as i32,
+                        p.x * cell_size as f64 + render_offset.x,
+                        p.y * cell_size as f64 + render_offset.y,
+                        cell_size as f64 / 6.0, &[0xFF, 0xFF, 0xFF, 0xFF]);
+                }
+            }
+            Segm::Circle(x, y, radius) => {
+                pixels_primitives::circle(screen, config.width as i32,
+                    x * cell_size as f64 + render_offset.x,
+                    y * cell_size as f64 + render_offset.y,
+                    radius * cell_size as f64, 1.0, &[0xAA, 0xAA, 0xAA, 0xFF]);
+            }
         }
-
     }
 }
 
 // A neater way of invoking pixels_primitves functions
-fn draw_line(screen: &mut [u8], pos_a: Vector2<f64>, pos_b: Vector2<f64>, col: &[u8; 4]) {
-    pixels_primitives::line(screen, WIDTH as i32, pos_a.x, pos_a.y, pos_b.x, pos_b.y, col);
+fn draw_line(screen: &mut [u8], config: &RenderConfig, pos_a: Vector2<f64>, pos_b: Vector2<f64>, col: &[u8; 4]) {
+    pixels_primitives::line(screen, config.width as i32, pos_a.x, pos_a.y, pos_b.x, pos_b.y, col);
 }
 
 // My own draw_rect function, doesn't do bounds checking but like,, just don't be stupid?? 
-fn draw_rect(screen: &mut [u8], x_0: usize, y_0: usize, x_1: usize, y_1: usize, col: &[u8; 4]) {
-    if x_0 > WIDTH_USIZE || x_1 > WIDTH_USIZE || y_0 > HEIGHT_USIZE || y_1 > HEIGHT_USIZE { return; }
+fn draw_rect(screen: &mut [u8], config: &RenderConfig, x_0: usize, y_0: usize, x_1: usize, y_1: usize, col: &[u8; 4]) {
+    if x_0 > config.width || x_1 > config.width || y_0 > config.height || y_1 > config.height { return; }
     for y in y_0..y_1 {
-        screen[(x_0+(y)*WIDTH_USIZE) * 4..(x_1+(y)*WIDTH_USIZE) * 4].copy_from_slice(&col.repeat(x_1-x_0));
+        screen[(x_0+(y)*config.width) * 4..(x_1+(y)*config.width) * 4].copy_from_slice(&col.repeat(x_1-x_0));
     }
 }
 
@@ -1,7 +1,7 @@
 use nalgebra::{Vector, Vector2};
 use rand::Rng;
 
-use crate::{WIDTH, game::Game};
+use crate::game::{map::DEFAULT_HEIGHT, Game};
 
 pub mod shape;
 
@@ -9,14 +9,60 @@ pub mod shape;
 // the hit point, how far away it was, and if it hit x or y!
 // (cell, hit_pos, distance, texture_along, side)
 
-// (cell, distance, texture_along, brightness, side)
-type RaycastResult = Option<(usize, f64, f64, u8, u8)>;
+// (cell, distance, texture_along, normal, side)
+// The normal is which way the surface that got hit faces, pointing back towards the ray
+pub type RaycastHit = (usize, f64, f64, Vector2<f64>, u8);
+type RaycastResult = Option<RaycastHit>;
 
+// Returns the first thing the ray hits
 pub fn raycast(game: &Game, start_pos: Vector2<f64>, dir: Vector2<f64>, max_dist: f64) -> RaycastResult {
+    let mut result = None;
+    cast(game, start_pos, dir, max_dist, |hit| {
+        result = Some(hit);
+        false
+    });
+    result
+}
+
+// Returns every surface the ray hits (nearest first), up until nothing further along could be seen any more.
+// Used for drawing things like grates and windows, and taller walls poking out from behind shorter ones, where we need to draw what's behind them too.
+// 'eye_height' is how high up the player's eyes are in blocks (0.5 without any head bob), for working out what can be seen over the top of walls
+pub fn raycast_all(game: &Game, start_pos: Vector2<f64>, dir: Vector2<f64>, max_dist: f64, eye_height: f64) -> Vec<RaycastHit> {
+    let mut hits = Vec::new();
+    let mut visible_to = max_dist;
+    cast(game, start_pos, dir, max_dist, |hit| {
+        if hit.1 > visible_to { return false; }
+        hits.push(hit);
+        visible_to = visible_to.min(hidden_past(game, hit, eye_height));
+        !hides_everything_behind(game, hit.0)
+    });
+    hits
+}
+
+// How far away a wall behind this hit has to be before it can't poke out over the top of it, even if it's the tallest wall on the map.
+// The top of a wall shows up (height - eye_height) / distance above the horizon, so further away walls need to be taller to be seen
+fn hidden_past(game: &Game, (cell, distance, ..): RaycastHit, eye_height: f64) -> f64 {
+    let cell = game.map.get(cell);
+    let above_eyes = cell.wall_height() - eye_height;
+    // We can see over the top of it, or through it
+    if above_eyes <= 0.0 || game.textures[cell.texture_index as usize].see_through() { return f64::INFINITY; }
+    let tallest = game.map.tallest_wall as f64 / DEFAULT_HEIGHT as f64 - eye_height;
+    // Plus a bit so walls right on the edge still get drawn, the wall heights get rounded to whole pixels
+    distance * tallest / above_eyes + 1.0
+}
+
+// If a wall in this cell covers up everything behind it, so it's solid and nothing else on the map is taller
+pub fn hides_everything_behind(game: &Game, cell: usize) -> bool {
+    let cell = game.map.get(cell);
+    !game.textures[cell.texture_index as usize].see_through() && cell.height >= game.map.tallest_wall
+}
+
+// Walks along the ray, calling 'on_hit' for each surface it hits (nearest first) until 'on_hit' returns false.
+fn cast(game: &Game, start_pos: Vector2<f64>, dir: Vector2<f64>, max_dist: f64, mut on_hit: impl FnMut(RaycastHit) -> bool) {
     // If the ray is out of bounds, don't bother.
     if  start_pos.x < 0.0 || start_pos.x > game.map.width  as f64 ||
         start_pos.y < 0.0 || start_pos.y > game.map.height as f64 {
-        return None;
+        return;
     }
 
     // Which box of the map we're in
@@ -82,15 +128,20 @@ pub fn raycast(game: &Game, start_pos: Vector2<f64>, dir: Vector2<f64>, max_dist
                     texture_along = (start_pos + perp_dist * dir).x.rem_euclid(1.0);
                 }
                 
-                return Some((tile_index, perp_dist, texture_along, side * 64 + 191, side));
+                // Faces of a block point straight along whichever axis we crossed, back the way the ray came
+                let normal = match side {
+                    0 => Vector2::new(-dir.x.signum(), 0.0),
+                    _ => Vector2::new(0.0, -dir.y.signum()),
+                };
+                if !on_hit((tile_index, perp_dist, texture_along, normal, side)) { return; }
             }
             // Other shape...
             _ => {
                 // let shape_result = calc_shape_hit_info(game, tile_index, dir, map_pos, start_pos, game.map.get(tile_index));
-                let shape_result = shape::shape_hit(game, t, tile_index, map_pos, start_pos, dir);
-                if let Some((distance, texture_along, brightness)) = shape_result {
+                // A ray can hit more than one side of a shape (e.g. both sides of a thick wall)
+                for (distance, texture_along, normal) in shape::shape_hit(&game.map, tile_index, map_pos, start_pos, dir) {
                     let perp_dist = distance*dir.angle(&game.player.dir).cos();
-                    return Some((tile_index, perp_dist, texture_along, brightness, side));
+                    if !on_hit((tile_index, perp_dist, texture_along, normal, side)) { return; }
                 }
             }
         }
@@ -118,6 +169,4 @@ pub fn raycast(game: &Game, start_pos: Vector2<f64>, dir: Vector2<f64>, max_dist
             side = 1;
         }
     }
-
-    None
-}
\ No newline at end of file
+}
@@ -3,171 +3,163 @@ use std::ops::Add;
 use nalgebra::{distance, point, Point2, Vector2};
 use rand::{thread_rng, Rng};
 
-use crate::game::{map::{Cell, DoorState}, Game};
+use crate::game::map::Map;
 
-type HitPoint = (Point2<f64>, f64, u8);
+// Where it hit, how far along the line, and which way the line faces
+type HitPoint = (Point2<f64>, f64, Vector2<f64>);
 
 pub type Segment = [Point2<f64>; 2];
 
-// What i want to do:
-// store all shapes in some easy form, like a vector of segments
-// I don't want to regenerate them each time so I've got to hard-code the segments and generate their equations
-// Shapes are made up of lines (and maybe in the future curves, i could have an enum that has varients like line, x_line, y_line
-// (for a line, a line on the x axis, a line on the y axis, etc) then i could have a function that takes in the enum and does stuff
-
+mod expr;
+mod registry;
 
+pub use registry::{Bitfield, DirectionMode, ShapeDef, ShapeRegistry, SHAPES_PATH};
 
+// The things shapes are made of, in cell coordinates (0 to 1 across the cell). See registry.rs for how they're loaded
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Shape {
     Line(f64, f64, f64, f64),   // pos a, pos b
-    LineX(f64, f64, f64),       // along, from
-    LineY(f64, f64, f64),       // along, from
+    TexturedLine(f64, f64, f64, f64, f64, f64), // pos a, pos b, where the texture starts and ends along it
+    LineX(f64, f64, f64),       // x, from y, to y
+    LineY(f64, f64, f64),       // y, from x, to x
     Circle(f64, f64, f64),      // center, radius
+    Quad([(f64, f64); 4]),      // corners, going round it in order
 }
 
-pub fn generate_shape_vector(_cell: &Cell) {
-    type CompoundShape = (Vec<Vec<Shape>>, usize); // shapes, rotation mode
-    let shape_defs: Vec<CompoundShape> = vec![
-        (vec![vec![Shape::LineX(0.5, 0.0, 1.0)]], 2), // Thin wall
-        // Pillar
-        (vec![
-            // 0.5 thick
-            vec![
-                Shape::LineX(0.25, 0.25, 0.75),
-                Shape::LineX(0.75, 0.25, 0.75),
-                Shape::LineY(0.25, 0.25, 0.75),
-                Shape::LineY(0.75, 0.25, 0.75),
-            ],
-            // 0.2 thick
-            vec![
-                Shape::LineX(0.4, 0.4, 0.6),
-                Shape::LineX(0.6, 0.4, 0.6),
-                Shape::LineY(0.4, 0.4, 0.6),
-                Shape::LineY(0.6, 0.4, 0.6),
-            ],
-        ], 0),
-
-        (vec![vec![Shape::LineX(0.0, 0.0, 1.0), Shape::LineY(0.0, 0.0, 1.0), Shape::Line(0.0, 1.0, 1.0, 0.0)]], 1), // Diagonal
-    ];
-}
+impl Shape {
+    // A box from one corner to the other, turned 'angle' degrees clockwise around its middle
+    pub fn quad(x1: f64, y1: f64, x2: f64, y2: f64, angle: f64) -> Shape {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        // So quarter turns come out exactly on the grid
+        let (sin, cos) = (snap(sin), snap(cos));
+        let (mid_x, mid_y) = ((x1 + x2) / 2.0, (y1 + y2) / 2.0);
+        let turn = |(x, y): (f64, f64)| {
+            let (x, y) = (x - mid_x, y - mid_y);
+            (mid_x + x * cos - y * sin, mid_y + x * sin + y * cos)
+        };
+        Shape::Quad([(x1, y1), (x2, y1), (x2, y2), (x1, y2)].map(turn))
+    }
 
-trait SetIfSmaller {
-    fn set_if_smaller(&mut self, pos: Point2<f64>, other: Option<HitPoint>);
-}
+    // Moves every point of the shape. LineX/LineY stay lined up with the cell if they're still along an axis,
+    // other lines keep their texture stretched along them
+    pub fn map_points(self, f: impl Fn((f64, f64)) -> (f64, f64)) -> Shape {
+        match self {
+            Shape::Line(x1, y1, x2, y2) => {
+                let ((x1, y1), (x2, y2)) = (f((x1, y1)), f((x2, y2)));
+                Shape::Line(x1, y1, x2, y2)
+            }
+            Shape::TexturedLine(x1, y1, x2, y2, tex_from, tex_to) => {
+                let ((x1, y1), (x2, y2)) = (f((x1, y1)), f((x2, y2)));
+                Shape::TexturedLine(x1, y1, x2, y2, tex_from, tex_to)
+            }
+            Shape::LineX(x, from, to) => Shape::grid_line(f((x, from)), f((x, to))),
+            Shape::LineY(y, from, to) => Shape::grid_line(f((from, y)), f((to, y))),
+            Shape::Circle(x, y, r) => {
+                let (x, y) = f((x, y));
+                Shape::Circle(x, y, r)
+            }
+            Shape::Quad(corners) => Shape::Quad(corners.map(f)),
+        }
+    }
+
+    // Turns it a quarter turn clockwise around the middle of the cell, (x, y) -> (1-y, x)
+    pub fn turned(self) -> Shape {
+        self.map_points(|(x, y)| (1.0 - y, x))
+    }
+
+    // Swaps x and y, so N/S shapes become E/W
+    pub fn flipped(self) -> Shape {
+        self.map_points(|(x, y)| (y, x))
+    }
 
-impl SetIfSmaller for Option<HitPoint> {
-    fn set_if_smaller(&mut self, pos: Point2<f64>, other: Option<HitPoint>) {
-        if other.is_none() { return; }
-        if self.is_none() {
-            *self = other;
-            return;
+    // Turns it to face whichever way 'direction' says, depending on the direction mode (see registry.rs)
+    pub fn facing(self, mode: DirectionMode, direction: u8) -> Shape {
+        match mode {
+            DirectionMode::Fixed   => self,
+            DirectionMode::FourWay => (0..direction % 4).fold(self, |shape, _| shape.turned()),
+            DirectionMode::TwoWay  => match direction & 1 {
+                0 => self,
+                _ => self.flipped(),
+            },
         }
-        let my_dist    = na::distance(&pos, &self .unwrap().0);
-        let other_dist = na::distance(&pos, &other.unwrap().0);
-        if other_dist < my_dist {
-            *self = other;
+    }
+
+    // A line between two points, which is a LineX/LineY if it's along an axis
+    fn grid_line((x1, y1): (f64, f64), (x2, y2): (f64, f64)) -> Shape {
+        match (x1 == x2, y1 == y2) {
+            (true, _) => Shape::LineX(x1, y1, y2),
+            (_, true) => Shape::LineY(y1, x1, x2),
+            _ => Shape::Line(x1, y1, x2, y2),
         }
     }
+
+    // The sides of a quad as lines, the two going down first then the two going across
+    fn edges(corners: [(f64, f64); 4]) -> [Shape; 4] {
+        [(0, 3), (1, 2), (0, 1), (3, 2)].map(|(a, b)| Shape::grid_line(corners[a], corners[b]))
+    }
+}
+
+// Rounds away the tiny bit of error from sin and cos, so they're exactly 0 or 1 when they should be
+fn snap(n: f64) -> f64 {
+    match (n - n.round()).abs() < 1e-12 {
+        true  => n.round(),
+        false => n,
+    }
 }
 
-pub fn shape_hit(game: &Game, cell: &Cell, tile_index: usize, map_pos: Vector2<usize>, ray_pos: Vector2<f64>, ray_dir: Vector2<f64>) -> Option<(f64, f64, u8)>{
+// Returns every surface of the shape that the ray hits, nearest first, as (distance, texture_along, normal)
+// The normal always points back towards the ray, so it's the side of the surface we can see
+pub fn shape_hit(map: &Map, tile_index: usize, map_pos: Vector2<usize>, ray_pos: Vector2<f64>, ray_dir: Vector2<f64>) -> Vec<(f64, f64, Vector2<f64>)>{
     let map_pos_f = point![map_pos.x as f64, map_pos.y as f64];
     // STILL need to make everything use points instead of Vector2.. so this will do for now
     let local_ray_pos = point![ray_pos.x - map_pos_f.x, ray_pos.y - map_pos_f.y];
     // Precalculated as it's probably a teeny bit faster that way
     let ray_grad = ray_dir.y / ray_dir.x;
 
-    let mut hit: Option<HitPoint> = None;
+    let mut hits: Vec<HitPoint> = Vec::new();
     let ray_pos_p = na::point![ray_pos.x, ray_pos.y];
 
-    match cell.kind {
-        // DOOR
-        3 => {
-            let amount = match *game.map.doors.get(&tile_index).unwrap() {
-                DoorState::Closed   => { 1.0 }
-                DoorState::Open(..) => { 0.0 }
-                DoorState::Closing(a) => { 1.0 - a*2.0 }
-                DoorState::Opening(a) => { a*2.0 }
-            };
-
-            let orientation = cell.flags & 0b00000001 == 0;
-
-            match (cell.flags & 0b00001100) >> 2 {
-            // SLIDE DOOR
-            0 => {
-                // The left and the right of the door
-                // Changes depending on the flipped flag
-                let door_sides = match (cell.flags & 0b00000010) >> 1 {
-                    0 => [amount, amount-1.0],
-                    _ => [1.0-amount, 2.0-amount],
-                };
-
-                // Two long sides
-                hit.set_if_smaller(ray_pos_p, line_axis(orientation, (1.0, 0.0), local_ray_pos, ray_dir, ray_grad, 0.6, door_sides, map_pos_f));
-                hit.set_if_smaller(ray_pos_p, line_axis(orientation, (1.0, 0.0), local_ray_pos, ray_dir, ray_grad, 0.4, door_sides, map_pos_f));
-                // Short bit
-                hit.set_if_smaller(ray_pos_p, line_axis(!orientation, (0.2, 0.4), local_ray_pos, ray_dir, ray_grad, door_sides[0], [0.4, 0.6], map_pos_f));
-            },
-            // ELEVATOR DOOR
-            1 => {
-                let door_parts = [[amount / 2.0, (amount - 1.0) / 2.0], [1.0-(amount / 2.0), 1.5-(amount / 2.0)]];
-                hit.set_if_smaller(ray_pos_p, line_axis(orientation, (0.5, 0.0), local_ray_pos, ray_dir, ray_grad, 0.55, door_parts[0], map_pos_f));
-                hit.set_if_smaller(ray_pos_p, line_axis(orientation, (0.5, 0.0), local_ray_pos, ray_dir, ray_grad, 0.45, door_parts[0], map_pos_f));
-                hit.set_if_smaller(ray_pos_p, line_axis(orientation, (0.5, 0.5), local_ray_pos, ray_dir, ray_grad, 0.55, door_parts[1], map_pos_f));
-                hit.set_if_smaller(ray_pos_p, line_axis(orientation, (0.5, 0.5), local_ray_pos, ray_dir, ray_grad, 0.45, door_parts[1], map_pos_f));
-                // Short bit
-                hit.set_if_smaller(ray_pos_p, line_axis(!orientation, (4.0 / 128.0, 62.0 / 128.0), local_ray_pos, ray_dir, ray_grad, door_parts[0][0], [0.45, 0.55], map_pos_f));
-                hit.set_if_smaller(ray_pos_p, line_axis(!orientation, (4.0 / 128.0, 62.0 / 128.0), local_ray_pos, ray_dir, ray_grad, door_parts[1][0], [0.45, 0.55], map_pos_f));
-            }
-            _ => {}
-            }
-            
-        }
-        // THIN WALL
-        4 => {
-            let orientation = cell.flags & 0b00000001 == 0;
-            hit.set_if_smaller(ray_pos_p, line_axis(orientation, (1.0, 0.0), local_ray_pos, ray_dir, ray_grad, 0.5, [0.0, 1.0], map_pos_f));
-        }
-        // THICK WALL
-        5 => {
-            let orientation = cell.flags & 0b00000001 == 0;
-            // Long bits
-            hit.set_if_smaller(ray_pos_p, line_axis(orientation, (1.0, 0.0), local_ray_pos, ray_dir, ray_grad, 0.6, [0.0, 1.0], map_pos_f));
-            hit.set_if_smaller(ray_pos_p, line_axis(orientation, (1.0, 0.0), local_ray_pos, ray_dir, ray_grad, 0.4, [0.0, 1.0], map_pos_f));
-            // Short bits
-            // TODO: Make a better way to manipulate the values maybe..
-            hit.set_if_smaller(ray_pos_p, line_axis(!orientation, (0.2, 0.4), local_ray_pos, ray_dir, ray_grad, 0.0, [0.4, 0.6], map_pos_f));
-            hit.set_if_smaller(ray_pos_p, line_axis(!orientation, (0.2, 0.4), local_ray_pos, ray_dir, ray_grad, 1.0, [0.4, 0.6], map_pos_f));
-        }
-        // SQUARE PILLAR
-        6 => {
-            hit.set_if_smaller(ray_pos_p, line_axis(true , (0.5, 0.25), local_ray_pos, ray_dir, ray_grad, 0.25, [0.25, 0.75], map_pos_f));
-            hit.set_if_smaller(ray_pos_p, line_axis(true , (0.5, 0.25), local_ray_pos, ray_dir, ray_grad, 0.75, [0.25, 0.75], map_pos_f));
-            hit.set_if_smaller(ray_pos_p, line_axis(false, (0.5, 0.25), local_ray_pos, ray_dir, ray_grad, 0.25, [0.25, 0.75], map_pos_f));
-            hit.set_if_smaller(ray_pos_p, line_axis(false, (0.5, 0.25), local_ray_pos, ray_dir, ray_grad, 0.75, [0.25, 0.75], map_pos_f));
-        }
-        // ROUND PILLAR
-        7 => {
-            return None;
-        }
-        // DIAGONAL
-        8 => {
-            let orientation = cell.flags & 0b00000001 == 0;
-            let positions = match orientation {
-                true  => [point![0.0, 0.0], point![1.0, 1.0]],
-                false => [point![1.0, 0.0], point![0.0, 1.0]],
-            };
-            hit.set_if_smaller(ray_pos_p, line(local_ray_pos, ray_dir, ray_grad, positions, map_pos_f));
-        }
-        _ => { return None }
+    for &shape in map.cell_shapes(tile_index) {
+        hit_shape(shape, local_ray_pos, ray_dir, ray_grad, map_pos_f, &mut hits);
     }
 
-    if hit.is_none() { return None; }
-    return Some((na::distance(&ray_pos_p, &hit.unwrap().0), hit.unwrap().1, hit.unwrap().2));
+    let mut hits: Vec<(f64, f64, Vector2<f64>)> = hits.iter()
+        .map(|&(point, along, normal)| {
+            let normal = match normal.dot(&ray_dir) > 0.0 {
+                true  => -normal,
+                false =>  normal,
+            };
+            (na::distance(&ray_pos_p, &point), along, normal)
+        })
+        .collect();
+    hits.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+    hits
 
 }
 
-fn quad(ray_pos: Point2<f64>, ray_dir: Vector2<f64>, ray_grad: f64, intercept: f64, rect_start: Point2<f64>) {
+// Adds wherever the ray hits the shape to 'hits'
+fn hit_shape(shape: Shape, ray_pos: Point2<f64>, ray_dir: Vector2<f64>, ray_grad: f64, map_pos: Point2<f64>, hits: &mut Vec<HitPoint>) {
+    match shape {
+        // Axis lines have the texture lined up with the cell, so it matches up between neighbouring cells
+        Shape::LineX(x, from, to) => hits.extend(line_axis(true,  ((to - from).abs(), from.min(to)), ray_pos, ray_dir, ray_grad, x, [from, to], map_pos)),
+        Shape::LineY(y, from, to) => hits.extend(line_axis(false, ((to - from).abs(), from.min(to)), ray_pos, ray_dir, ray_grad, y, [from, to], map_pos)),
+        Shape::Line(x1, y1, x2, y2) => hits.extend(line(ray_pos, ray_dir, ray_grad, [point![x1, y1], point![x2, y2]], map_pos)),
+        Shape::TexturedLine(x1, y1, x2, y2, tex_from, tex_to) => {
+            hits.extend(line(ray_pos, ray_dir, ray_grad, [point![x1, y1], point![x2, y2]], map_pos).map(|(point, along, normal)| {
+                (point, tex_from + along * (tex_to - tex_from), normal)
+            }));
+        }
+        // Circles can be hit on both sides
+        Shape::Circle(x, y, radius) => hits.extend(circle(ray_pos, ray_dir, point![x, y], radius, map_pos)),
+        Shape::Quad(corners) => quad(ray_pos, ray_dir, ray_grad, corners, map_pos, hits),
+    }
+}
 
+// A box, which is just its 4 sides. Sides that line up with the grid get hit the quick way, same as any other line
+fn quad(ray_pos: Point2<f64>, ray_dir: Vector2<f64>, ray_grad: f64, corners: [(f64, f64); 4], map_pos: Point2<f64>, hits: &mut Vec<HitPoint>) {
+    for edge in Shape::edges(corners) {
+        hit_shape(edge, ray_pos, ray_dir, ray_grad, map_pos, hits);
+    }
 }
 
 // A line on an axis 
@@ -206,7 +198,7 @@ fn line_x(ray_pos: Point2<f64>, ray_dir: Vector2<f64>, ray_grad: f64, y_intercep
     if point_behind_ray(ray_pos, ray_dir, x_intercept, y_intercept) { return None; }
 
     let along = (x_intercept - f64::min(line_bounds[0], line_bounds[1])) / f64::abs(line_bounds[1] - line_bounds[0]);
-    Some((point![map_pos.x + x_intercept, map_pos.y + y_intercept], along, 255))
+    Some((point![map_pos.x + x_intercept, map_pos.y + y_intercept], along, Vector2::new(0.0, 1.0)))
 }
 
 // A line on the Y axis.
@@ -224,17 +216,21 @@ fn line_y(ray_pos: Point2<f64>, ray_dir: Vector2<f64>, ray_grad: f64, x_intercep
     if point_behind_ray(ray_pos, ray_dir, x_intercept, y_intercept) { return None; }
 
     let along = (y_intercept - f64::min(line_bounds[0], line_bounds[1])) / f64::abs(line_bounds[1] - line_bounds[0]);
-    Some((point![map_pos.x + x_intercept, map_pos.y + y_intercept], along, 192))
+    Some((point![map_pos.x + x_intercept, map_pos.y + y_intercept], along, Vector2::new(1.0, 0.0)))
 }
 
 // Returns if/where the ray hit a given line. (as well as how far along :3)
 // from 0 - 1 inside a cell.
 fn line(ray_pos: Point2<f64>, ray_dir: Vector2<f64>, ray_grad: f64, line_points: [Point2<f64>; 2], map_pos: Point2<f64>) -> Option<HitPoint> {
     // If the line is straight along the x axis or y axis, check it the quick (and less error-prone) way.
-    if line_points[0].x == line_points[1].x {
-        return line_y(ray_pos, ray_dir, ray_grad, line_points[0].x, [line_points[0].y, line_points[1].y], map_pos);
+    // Those measure 'along' from the lower end, but here it has to be from the first point so the texture turns with the line
+    let quick = if line_points[0].x == line_points[1].x {
+        Some((line_y(ray_pos, ray_dir, ray_grad, line_points[0].x, [line_points[0].y, line_points[1].y], map_pos), line_points[0].y > line_points[1].y))
     } else if line_points[0].y == line_points[1].y {
-        return line_x(ray_pos, ray_dir, ray_grad, line_points[0].y, [line_points[0].x, line_points[1].x], map_pos);
+        Some((line_x(ray_pos, ray_dir, ray_grad, line_points[0].y, [line_points[0].x, line_points[1].x], map_pos), line_points[0].x > line_points[1].x))
+    } else { None };
+    if let Some((hit, backwards)) = quick {
+        return hit.map(|(point, along, normal)| (point, if backwards { 1.0 - along } else { along }, normal));
     }
 
     let line_grad = (line_points[1].y - line_points[0].y) / (line_points[1].x - line_points[0].x);
@@ -265,8 +261,35 @@ fn line(ray_pos: Point2<f64>, ray_dir: Vector2<f64>, ray_grad: f64, line_points:
     // if ray_dir.x.is_sign_negative() || ray_dir.y.is_sign_positive() {
     //     along = 1.0-along;
     // }
-    // TODO: work out brightness
-    Some((point![map_pos.x + x_intercept, map_pos.y + y_intercept], along, 255))
+    // Perpendicular to the line, shape_hit() flips it round to face the ray
+    let line_dir = line_points[1] - line_points[0];
+    let normal = Vector2::new(-line_dir.y, line_dir.x).normalize();
+    Some((point![map_pos.x + x_intercept, map_pos.y + y_intercept], along, normal))
+}
+
+// Returns where the ray goes in and out of a circle, if it does
+// 'along' goes around the circle, wrapping the texture round a whole number of times so there's no seam
+fn circle(ray_pos: Point2<f64>, ray_dir: Vector2<f64>, center: Point2<f64>, radius: f64, map_pos: Point2<f64>) -> Vec<HitPoint> {
+    // Solving |ray_pos + ray_dir*t - center| = radius for t, which is a quadratic
+    let dir = ray_dir.normalize();
+    let to_ray = ray_pos - center;
+    let b = to_ray.dot(&dir);
+    let c = to_ray.magnitude_squared() - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 { return Vec::new(); }
+
+    let repeats = (std::f64::consts::TAU * radius).round().max(1.0);
+    [-b - discriminant.sqrt(), -b + discriminant.sqrt()].into_iter()
+        // Behind the ray.. we don't want it!!
+        .filter(|&t| t >= 0.0)
+        .map(|t| ray_pos + dir * t)
+        .filter(|hit| between_in_cell(hit.x, 0.0, 1.0) && between_in_cell(hit.y, 0.0, 1.0))
+        .map(|hit| {
+            let normal = (hit - center) / radius;
+            let along = (normal.y.atan2(normal.x) / std::f64::consts::TAU).rem_euclid(1.0) * repeats;
+            (point![map_pos.x + hit.x, map_pos.y + hit.y], along, normal)
+        })
+        .collect()
 }
 
 // Checks if the input is between two values, as well as making sure it's between 0.0 and 1.0
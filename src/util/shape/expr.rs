@@ -0,0 +1,141 @@
+// Little maths expressions for the numbers in shape definitions, like '0.5 + (variant + 1) / 10'
+// They can use + - * / and brackets, and any variables (a shape's bitfields, or ones it works out itself)
+
+use std::collections::HashMap;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Expr {
+    Number(f64),
+    Variable(String),
+    Negative(Box<Expr>),
+    // left, operator, right
+    Operation(Box<Expr>, char, Box<Expr>),
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, String> {
+        let tokens = tokenise(source)?;
+        let mut pos = 0;
+        let expr = parse_sum(&tokens, &mut pos)?;
+        match tokens.get(pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected '{token}' in '{source}'")),
+        }
+    }
+
+    pub fn eval(&self, variables: &HashMap<String, f64>) -> Result<f64, String> {
+        Ok(match self {
+            Expr::Number(n) => *n,
+            Expr::Variable(name) => *variables.get(name).ok_or_else(|| format!("there's no variable called '{name}'"))?,
+            Expr::Negative(expr) => -expr.eval(variables)?,
+            Expr::Operation(left, operator, right) => {
+                let (left, right) = (left.eval(variables)?, right.eval(variables)?);
+                match operator {
+                    '+' => left + right,
+                    '-' => left - right,
+                    '*' => left * right,
+                    _   => left / right,
+                }
+            }
+        })
+    }
+
+    // If the variable shows up anywhere in it
+    pub fn uses(&self, variable: &str) -> bool {
+        match self {
+            Expr::Number(_) => false,
+            Expr::Variable(name) => name == variable,
+            Expr::Negative(expr) => expr.uses(variable),
+            Expr::Operation(left, _, right) => left.uses(variable) || right.uses(variable),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Symbol(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{n}"),
+            Token::Name(name) => write!(f, "{name}"),
+            Token::Symbol(c) => write!(f, "{c}"),
+        }
+    }
+}
+
+fn tokenise(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        // Grabs characters for as long as they match
+        let mut take_while = |matches: fn(char) -> bool| {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek().filter(|&&c| matches(c)) {
+                word.push(c);
+                chars.next();
+            }
+            word
+        };
+        match c {
+            c if c.is_whitespace() => { take_while(char::is_whitespace); }
+            c if c.is_ascii_digit() || c == '.' => {
+                let number = take_while(|c| c.is_ascii_digit() || c == '.');
+                tokens.push(Token::Number(number.parse().map_err(|_| format!("'{number}' isn't a number"))?));
+            }
+            c if c.is_alphabetic() || c == '_' => tokens.push(Token::Name(take_while(|c| c.is_alphanumeric() || c == '_'))),
+            '+' | '-' | '*' | '/' | '(' | ')' => {
+                tokens.push(Token::Symbol(c));
+                chars.next();
+            }
+            _ => return Err(format!("'{c}' can't go in an expression")),
+        }
+    }
+    Ok(tokens)
+}
+
+// a + b - c ...
+fn parse_sum(tokens: &[Token], pos: &mut usize) -> Result<Expr, String> {
+    let mut expr = parse_product(tokens, pos)?;
+    while let Some(&Token::Symbol(operator @ ('+' | '-'))) = tokens.get(*pos) {
+        *pos += 1;
+        expr = Expr::Operation(Box::new(expr), operator, Box::new(parse_product(tokens, pos)?));
+    }
+    Ok(expr)
+}
+
+// a * b / c ...
+fn parse_product(tokens: &[Token], pos: &mut usize) -> Result<Expr, String> {
+    let mut expr = parse_value(tokens, pos)?;
+    while let Some(&Token::Symbol(operator @ ('*' | '/'))) = tokens.get(*pos) {
+        *pos += 1;
+        expr = Expr::Operation(Box::new(expr), operator, Box::new(parse_value(tokens, pos)?));
+    }
+    Ok(expr)
+}
+
+// A number, a variable, something in brackets or minus any of those
+fn parse_value(tokens: &[Token], pos: &mut usize) -> Result<Expr, String> {
+    let token = tokens.get(*pos).ok_or("expression ended too soon")?;
+    *pos += 1;
+    match token {
+        Token::Number(n) => Ok(Expr::Number(*n)),
+        Token::Name(name) => Ok(Expr::Variable(name.clone())),
+        Token::Symbol('-') => Ok(Expr::Negative(Box::new(parse_value(tokens, pos)?))),
+        Token::Symbol('(') => {
+            let expr = parse_sum(tokens, pos)?;
+            match tokens.get(*pos) {
+                Some(Token::Symbol(')')) => {
+                    *pos += 1;
+                    Ok(expr)
+                }
+                _ => Err(String::from("missing a ')'")),
+            }
+        }
+        Token::Symbol(c) => Err(format!("unexpected '{c}'")),
+    }
+}
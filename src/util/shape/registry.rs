@@ -0,0 +1,551 @@
+// Loads the shapes of every kind of cell from a text file (res/shapes.txt), so new wall pieces don't need any code.
+//
+// Each shape starts with 'id : name : direction mode', followed by the things it's made of, one per line:
+//     line_x x  y1 y2        a line at x, going from y1 to y2
+//     line_y y  x1 x2        a line at y, going from x1 to x2
+//     line   x1 y1  x2 y2    a line between any two points, with the texture stretched along it from the first point
+//     line   x1 y1  x2 y2  t1 t2   the same, but the texture goes from t1 to t2 along it instead of 0 to 1
+//     quad   x1 y1  x2 y2    a box from one corner to the other
+//     quad   x1 y1  x2 y2  a  the same box, turned 'a' degrees clockwise around its middle
+//     circle x y  radius
+// Everything's in cell coordinates, so 0 to 1 across the cell. '//' starts a comment.
+// A line starting with '#' starts a new variant (anything after it is ignored), the variant a cell uses comes from its flags.
+//
+// Numbers can also be little sums (see expr.rs), using the shape's bitfields and any variables it works out first:
+//     5 : ThickWall : 2
+//         variants: 4                         the shapes below make 4 variants, with 'variant' going from 0 to 3
+//         variables:
+//             t = (variant + 1) / 10          half of how thick it is
+//         quad (0.5 - t) 0.0  (0.5 + t) 1.0   sums with spaces in need to go in brackets
+// Variables are worked out in order, once for each variant when the shapes are loaded.
+// Without 'variants:', each '#' variant gets worked out with its own number. Either way there can't be more than the 'variant' bitfield can pick from.
+// Shapes that use 'open' (how open a door is, 0 shut to 1 open) are loaded shut, and get worked out again as they move (see ShapeRegistry::get_open)
+//
+// The lowest 2 bits of a cell's flags say which way it's facing ('rotation'), and the other 6 bits which variant it is ('variant').
+// A shape can split its flags up differently by naming the bitfields after the direction mode, lowest bits first,
+// e.g. '10 : Fan : 0 : speed 2, colour 6'. Then code can just ask for the 'speed' of a cell instead of masking it out.
+// How a shape faces different ways depends on its direction mode, every kind of shape gets turned or flipped the same way (see Shape::facing):
+//     0: doesn't turn, it's always the same
+//     1: turns to face N, E, S or W
+//     2: is either N/S or E/W, e.g. a thin wall
+//
+// Shapes can also be loaded from JSON (anything ending in .json), which looks like this:
+//     {
+//         "8": {
+//             "name": "Diagonal",
+//             "direction_mode": 1,                       (optional, 0 if it's not there)
+//             "bitfields": {                             (optional, rotation 2 and variant 6 if it's not there)
+//                 "rotation": { "length": 2 },           packed from the lowest bit in the order they're written,
+//                 "variant":  { "length": 6 }            or from "start" if it's given
+//             },
+//             "variants": 1,                             (optional, same as 'variants:')
+//             "variables": { "t": "variant / 10" },      (optional, same as 'variables:')
+//             "shapes": [ { "line": [0.0, 0.0, 1.0, 1.0] } ]
+//         }
+//     }
+// "shapes" is either a list of shapes, or a list of lists of shapes, one for each variant.
+// Each shape has the same name and numbers as in the text format, and numbers can be sums written as strings.
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use super::{expr::Expr, Shape};
+
+pub const SHAPES_PATH: &str = "res/shapes.txt";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DirectionMode {
+    Fixed,
+    FourWay,
+    TwoWay,
+}
+
+// A named group of bits in a cell's flags
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Bitfield {
+    pub name: String,
+    pub start: u8,
+    pub length: u8,
+}
+
+impl Bitfield {
+    pub fn new(name: &str, start: u8, length: u8) -> Bitfield {
+        Bitfield { name: String::from(name), start, length }
+    }
+
+    // Pulls this field's value out of the flags
+    pub fn get(&self, flags: u8) -> u8 {
+        ((flags as u16 >> self.start) & ((1 << self.length) - 1)) as u8
+    }
+}
+
+pub struct ShapeDef {
+    pub name: String,
+    pub direction_mode: DirectionMode,
+    // How the flags are split up, 'rotation' and 'variant' are used to place the shapes
+    pub bitfields: Vec<Bitfield>,
+    // Shapes for each variant, as they're written in the file (facing the first way)
+    pub variants: Vec<Vec<Shape>>,
+    // Kept for shapes that use 'open', so they can be worked out again as they move
+    moving: Option<Recipe>,
+}
+
+// A shape as it's written down, before its numbers have been worked out.
+// 'line' is where it came from in the text format, for errors
+struct Template {
+    kind: String,
+    numbers: Vec<Expr>,
+    line: Option<usize>,
+}
+
+// Everything needed to work out a shape's variants
+struct Recipe {
+    variables: Vec<(String, Expr, Option<usize>)>,
+    // How many variants to make from the shapes, if it's said
+    count: Option<usize>,
+    templates: Vec<Vec<Template>>,
+}
+
+// A shape definition while it's being read in, its variants get filled in by finish()
+struct Pending {
+    def: ShapeDef,
+    recipe: Recipe,
+}
+
+#[derive(Default)]
+pub struct ShapeRegistry {
+    defs: Vec<Option<ShapeDef>>,
+    // The shapes for every kind and every possible flags byte, already turned to face the right way,
+    // so raycasting just has to look them up. [kind][flags]
+    placed: Vec<Vec<Vec<Shape>>>,
+}
+
+impl ShapeRegistry {
+    // Loads the shapes from a file, printing out anything wrong with it
+    pub fn load(path: &str) -> ShapeRegistry {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("couldn't load shapes from {path}: {err}");
+                return ShapeRegistry::default();
+            }
+        };
+        let (registry, errors) = match path.ends_with(".json") {
+            true  => ShapeRegistry::parse_json(&source),
+            false => ShapeRegistry::parse(&source),
+        };
+        for err in errors {
+            eprintln!("{path}, {err}");
+        }
+        registry
+    }
+
+    // Reads shapes from the text format at the top of this file. Lines that don't make sense are skipped, and come back as errors
+    pub fn parse(source: &str) -> (ShapeRegistry, Vec<String>) {
+        let mut defs: Vec<Option<Pending>> = (0..=u8::MAX).map(|_| None).collect();
+        let mut errors = Vec::new();
+        let mut current: Option<u8> = None;
+
+        for (line_number, line) in source.lines().enumerate() {
+            let line = line.split("//").next().unwrap().trim();
+            if line.is_empty() { continue; }
+            let line_number = line_number + 1;
+
+            let is_header = line.contains(':') && !line.starts_with('#') && !line.starts_with("variants:") && line != "variables:" && !line.contains('=');
+            let parsed = match (is_header, current.and_then(|id| defs[id as usize].as_mut())) {
+                // New shape
+                (true, _) => {
+                    // If the header doesn't make sense, whatever's under it mustn't end up in the shape before
+                    current = None;
+                    parse_header(line).map(|(id, def)| {
+                        if defs[id as usize].is_some() { errors.push(format!("line {line_number}: shape {id} is defined twice")); }
+                        defs[id as usize] = Some(Pending::new(def));
+                        current = Some(id);
+                    })
+                }
+                (false, None) => Err(String::from("this is outside of a shape definition")),
+                // Something in the current shape
+                (false, Some(pending)) => match line {
+                    // New variant
+                    _ if line.starts_with('#') => {
+                        // Anything before the first '#' is the first variant, so only start another one if this one has something in it
+                        if !pending.recipe.templates.last().unwrap().is_empty() { pending.recipe.templates.push(Vec::new()); }
+                        Ok(())
+                    }
+                    // Just there to make things easier to read
+                    "variables:" => Ok(()),
+                    _ if line.starts_with("variants:") => {
+                        let count = line["variants:".len()..].trim();
+                        let max = pending.def.max_variants();
+                        match count.parse() {
+                            Ok(count) if (1..=max).contains(&count) => { pending.recipe.count = Some(count); Ok(()) }
+                            _ => Err(format!("'{count}' isn't a number of variants (1 to {max})")),
+                        }
+                    }
+                    _ if line.contains('=') => {
+                        let (name, expr) = line.split_once('=').unwrap();
+                        parse_variable(name.trim(), expr).map(|(name, expr)| pending.recipe.variables.push((name, expr, Some(line_number))))
+                    }
+                    _ => parse_shape(line).map(|(kind, numbers)| {
+                        pending.recipe.templates.last_mut().unwrap().push(Template { kind, numbers, line: Some(line_number) });
+                    }),
+                },
+            };
+            if let Err(err) = parsed {
+                errors.push(format!("line {line_number}: {err}"));
+            }
+        }
+
+        let defs = defs.into_iter().map(|pending| pending.map(|pending| {
+            let (def, def_errors) = pending.finish();
+            errors.extend(def_errors);
+            def
+        })).collect();
+        (ShapeRegistry::from_defs(defs), errors)
+    }
+
+    // Reads shapes from JSON, as described at the top of this file. Shapes that don't make sense are skipped, and come back as errors
+    pub fn parse_json(source: &str) -> (ShapeRegistry, Vec<String>) {
+        let mut defs: Vec<Option<ShapeDef>> = (0..=u8::MAX).map(|_| None).collect();
+        let mut errors = Vec::new();
+
+        let root = match serde_json::from_str::<Value>(source) {
+            Ok(Value::Object(root)) => root,
+            Ok(_) => return (ShapeRegistry::default(), vec![String::from("expected an object of shapes")]),
+            Err(err) => return (ShapeRegistry::default(), vec![err.to_string()]),
+        };
+        for (id, value) in root {
+            let parsed = id.parse::<u8>()
+                .map_err(|_| format!("'{id}' isn't a cell kind (0 to 255)"))
+                .and_then(|id| parse_json_def(&value).map(|def| (id, def)));
+            match parsed {
+                Ok((kind, pending)) => {
+                    let (def, def_errors) = pending.finish();
+                    errors.extend(def_errors.into_iter().map(|err| format!("shape {id}: {err}")));
+                    defs[kind as usize] = Some(def);
+                }
+                Err(err) => errors.push(format!("shape {id}: {err}")),
+            }
+        }
+
+        (ShapeRegistry::from_defs(defs), errors)
+    }
+
+    fn from_defs(defs: Vec<Option<ShapeDef>>) -> ShapeRegistry {
+        let placed = defs.iter().map(|def| match def {
+            Some(def) => (0..=u8::MAX).map(|flags| def.placed(flags)).collect(),
+            None => Vec::new(),
+        }).collect();
+        ShapeRegistry { defs, placed }
+    }
+
+    pub fn def(&self, kind: u8) -> Option<&ShapeDef> {
+        self.defs.get(kind as usize)?.as_ref()
+    }
+
+    // What a cell with this kind and flags is made of, nothing if it's not a shape we know about
+    pub fn get(&self, kind: u8, flags: u8) -> &[Shape] {
+        self.placed.get(kind as usize).and_then(|flags_shapes| flags_shapes.get(flags as usize)).map_or(&[], |shapes| shapes)
+    }
+
+    // What a cell is made of when it's 'open' of the way open (0 to 1), for doors. Shapes that don't move are the same as get()
+    pub fn get_open(&self, kind: u8, flags: u8, open: f64) -> Vec<Shape> {
+        match self.def(kind) {
+            Some(def) if def.moving.is_some() => def.placed_open(flags, open),
+            _ => self.get(kind, flags).to_vec(),
+        }
+    }
+
+    // The value of a named bitfield in a cell's flags, 0 if the shape doesn't have it
+    pub fn field(&self, kind: u8, flags: u8, name: &str) -> u8 {
+        self.def(kind).map_or(0, |def| def.field(flags, name))
+    }
+}
+
+// What shapes split their flags into if they don't say otherwise
+fn default_bitfields() -> Vec<Bitfield> {
+    vec![Bitfield::new("rotation", 0, 2), Bitfield::new("variant", 2, 6)]
+}
+
+impl ShapeDef {
+    // The value of a named bitfield in the flags, 0 if there isn't one called that
+    pub fn field(&self, flags: u8, name: &str) -> u8 {
+        self.bitfields.iter().find(|field| field.name == name).map_or(0, |field| field.get(flags))
+    }
+
+    // Every bitfield's name and value
+    pub fn fields(&self, flags: u8) -> Vec<(&str, u8)> {
+        self.bitfields.iter().map(|field| (field.name.as_str(), field.get(flags))).collect()
+    }
+
+    // How many variants the 'variant' bitfield can pick between
+    fn max_variants(&self) -> usize {
+        self.bitfields.iter().find(|field| field.name == "variant").map_or(1, |field| 1 << field.length)
+    }
+
+    // The flags a cell of this variant has when it's facing the first way
+    fn variant_flags(&self, variant: usize) -> u8 {
+        self.bitfields.iter().find(|field| field.name == "variant").map_or(0, |field| (variant << field.start) as u8)
+    }
+
+    // Works out the variant and direction from the flags and turns the shapes to match
+    fn placed(&self, flags: u8) -> Vec<Shape> {
+        let direction = self.field(flags, "rotation");
+        let Some(shapes) = self.variants.get(self.field(flags, "variant") as usize) else { return Vec::new() };
+        shapes.iter().map(|&shape| shape.facing(self.direction_mode, direction)).collect()
+    }
+
+    // The same as placed(), but worked out again for how open it is
+    fn placed_open(&self, flags: u8, open: f64) -> Vec<Shape> {
+        let Some(recipe) = &self.moving else { return self.placed(flags) };
+        let variant = self.field(flags, "variant") as usize;
+        if variant >= self.variants.len() { return Vec::new(); }
+        // Anything wrong with it was already said when it was loaded
+        recipe.shapes(self, variant, open, &mut |_, _| {}).into_iter()
+            .map(|shape| shape.facing(self.direction_mode, self.field(flags, "rotation")))
+            .collect()
+    }
+}
+
+impl Recipe {
+    // Works out the numbers of one variant's shapes, with 'open' being how open it is (for doors)
+    fn shapes(&self, def: &ShapeDef, variant: usize, open: f64, report: &mut impl FnMut(Option<usize>, String)) -> Vec<Shape> {
+        let templates = &self.templates[if self.count.is_some() { 0 } else { variant }];
+        let mut variables: HashMap<String, f64> = def.fields(def.variant_flags(variant)).into_iter()
+            .map(|(name, value)| (String::from(name), value as f64))
+            .collect();
+        variables.insert(String::from("variant"), variant as f64);
+        variables.insert(String::from("open"), open);
+        for (name, expr, line) in &self.variables {
+            match expr.eval(&variables) {
+                Ok(value) => { variables.insert(name.clone(), value); }
+                Err(err) => report(*line, err),
+            }
+        }
+        templates.iter().flat_map(|template| {
+            let numbers = template.numbers.iter().map(|n| n.eval(&variables)).collect::<Result<Vec<_>, _>>();
+            numbers.and_then(|numbers| build_shapes(&template.kind, &numbers)).unwrap_or_else(|err| {
+                report(template.line, err);
+                Vec::new()
+            })
+        }).collect()
+    }
+
+    // If any of the numbers depend on the variable
+    fn uses(&self, variable: &str) -> bool {
+        self.variables.iter().any(|(_, expr, _)| expr.uses(variable)) ||
+        self.templates.iter().flatten().any(|template| template.numbers.iter().any(|n| n.uses(variable)))
+    }
+}
+
+impl Pending {
+    fn new(def: ShapeDef) -> Pending {
+        Pending { def, recipe: Recipe { variables: Vec::new(), count: None, templates: vec![Vec::new()] } }
+    }
+
+    // Works out all the numbers for every variant
+    fn finish(self) -> (ShapeDef, Vec<String>) {
+        let Pending { mut def, recipe } = self;
+        let mut errors = Vec::new();
+        // The same mistake would come up for every variant, so only say it once
+        let mut report = |line: Option<usize>, err: String| {
+            let err = match line {
+                Some(line) => format!("line {line}: {err}"),
+                None => err,
+            };
+            if !errors.contains(&err) { errors.push(err); }
+        };
+        if recipe.count.is_some() && recipe.templates.len() > 1 {
+            report(None, format!("{} has '#' variants and 'variants:', only the first variant is used", def.name));
+        }
+        if recipe.templates.len() > def.max_variants() {
+            report(None, format!("{} has {} variants but its flags can only pick from {}", def.name, recipe.templates.len(), def.max_variants()));
+        }
+
+        // Shapes that move are loaded shut
+        let count = recipe.count.unwrap_or(recipe.templates.len());
+        def.variants = (0..count).map(|variant| recipe.shapes(&def, variant, 0.0, &mut report)).collect();
+        if recipe.uses("open") { def.moving = Some(recipe); }
+        (def, errors)
+    }
+}
+
+// 'id : name : direction mode', and maybe ': bitfields'
+fn parse_header(line: &str) -> Result<(u8, ShapeDef), String> {
+    let parts: Vec<&str> = line.split(':').map(str::trim).collect();
+    let (id, name, mode, bitfields) = match parts[..] {
+        [id, name, mode] => (id, name, mode, None),
+        [id, name, mode, bitfields] => (id, name, mode, Some(bitfields)),
+        _ => return Err(String::from("expected 'id : name : direction mode'")),
+    };
+
+    let id = id.parse().map_err(|_| format!("'{id}' isn't a cell kind (0 to 255)"))?;
+    let direction_mode = match mode {
+        "0" => DirectionMode::Fixed,
+        "1" => DirectionMode::FourWay,
+        "2" => DirectionMode::TwoWay,
+        _ => return Err(format!("'{mode}' isn't a direction mode (0, 1 or 2)")),
+    };
+    // 'name length, name length...'
+    let bitfields = match bitfields {
+        Some(bitfields) => pack_bitfields(bitfields.split(',').map(|field| {
+            match field.split_whitespace().collect::<Vec<_>>()[..] {
+                [name, length] => length.parse().map(|length| (name, None, length)).map_err(|_| format!("'{length}' isn't a bitfield length")),
+                _ => Err(format!("'{}' should be a bitfield name and length", field.trim())),
+            }
+        }).collect::<Result<Vec<_>, _>>()?)?,
+        None => default_bitfields(),
+    };
+    Ok((id, ShapeDef { name: String::from(name), direction_mode, bitfields, variants: Vec::new(), moving: None }))
+}
+
+// Lays the bitfields out one after the other from the lowest bit, unless they say where to start
+fn pack_bitfields(fields: Vec<(&str, Option<u8>, u8)>) -> Result<Vec<Bitfield>, String> {
+    let mut next = 0;
+    fields.into_iter().map(|(name, start, length)| {
+        let start = start.unwrap_or(next);
+        if length == 0 || start as u16 + length as u16 > 8 {
+            return Err(format!("bitfield '{name}' doesn't fit in the 8 flag bits"));
+        }
+        next = start + length;
+        Ok(Bitfield::new(name, start, length))
+    }).collect()
+}
+
+// 'name = expression'
+fn parse_variable(name: &str, expr: &str) -> Result<(String, Expr), String> {
+    match Expr::parse(name) {
+        Ok(Expr::Variable(name)) => Ok((name, Expr::parse(expr)?)),
+        _ => Err(format!("'{name}' can't be a variable name")),
+    }
+}
+
+// A number in JSON, either an actual number or a sum in a string
+fn parse_json_number(value: &Value) -> Result<Expr, String> {
+    match value {
+        Value::Number(n) => Ok(Expr::Number(n.as_f64().unwrap())),
+        Value::String(expr) => Expr::parse(expr),
+        _ => Err(format!("'{value}' isn't a number")),
+    }
+}
+
+// One shape from a JSON object like { "line_x": [0.5, 0.0, 1.0] }
+fn parse_json_shape(value: &Value) -> Result<Template, String> {
+    let Some((kind, numbers)) = value.as_object().filter(|shape| shape.len() == 1).and_then(|shape| shape.iter().next()) else {
+        return Err(format!("'{value}' should look like {{ \"line_x\": [0.5, 0.0, 1.0] }}"));
+    };
+    let numbers: Vec<Expr> = numbers.as_array()
+        .ok_or_else(|| format!("{kind} needs a list of numbers"))?
+        .iter()
+        .map(parse_json_number)
+        .collect::<Result<_, _>>()?;
+    check_numbers(kind, numbers.len())?;
+    Ok(Template { kind: kind.clone(), numbers, line: None })
+}
+
+fn parse_json_def(value: &Value) -> Result<Pending, String> {
+    let name = value["name"].as_str().ok_or("it needs a name")?;
+    let direction_mode = match value["direction_mode"].as_u64() {
+        None | Some(0) => DirectionMode::Fixed,
+        Some(1) => DirectionMode::FourWay,
+        Some(2) => DirectionMode::TwoWay,
+        Some(mode) => return Err(format!("'{mode}' isn't a direction mode (0, 1 or 2)")),
+    };
+    let bitfields = match value.get("bitfields") {
+        Some(Value::Object(fields)) => pack_bitfields(fields.iter().map(|(name, field)| {
+            let length = field["length"].as_u64().ok_or_else(|| format!("bitfield '{name}' needs a length"))?;
+            let start = field.get("start").map(|start| start.as_u64().ok_or_else(|| format!("bitfield '{name}' has a bad start"))).transpose()?;
+            // Anything too big gets caught by pack_bitfields
+            Ok((name.as_str(), start.map(|s| s.min(8) as u8), length.min(8) as u8))
+        }).collect::<Result<Vec<_>, String>>()?)?,
+        Some(_) => return Err(String::from("bitfields should be an object")),
+        None => default_bitfields(),
+    };
+
+    let def = ShapeDef { name: String::from(name), direction_mode, bitfields, variants: Vec::new(), moving: None };
+
+    // Only as many as the 'variant' bitfield can pick between
+    let max = def.max_variants() as u64;
+    let count = match value.get("variants").map(Value::as_u64) {
+        None => None,
+        Some(Some(count)) if (1..=max).contains(&count) => Some(count as usize),
+        Some(_) => return Err(format!("'{}' isn't a number of variants (1 to {max})", value["variants"])),
+    };
+    let variables = match value.get("variables") {
+        Some(Value::Object(variables)) => variables.iter().map(|(name, expr)| {
+            let expr = match expr {
+                Value::String(expr) => expr.clone(),
+                expr => expr.to_string(),
+            };
+            parse_variable(name, &expr).map(|(name, expr)| (name, expr, None))
+        }).collect::<Result<_, _>>()?,
+        Some(_) => return Err(String::from("variables should be an object")),
+        None => Vec::new(),
+    };
+
+    // Either one list of shapes, or a list for each variant
+    let parse_variant = |shapes: &[Value]| shapes.iter().map(parse_json_shape).collect::<Result<Vec<_>, _>>();
+    let shapes = value["shapes"].as_array().map_or(&[][..], |shapes| shapes);
+    let templates = match !shapes.is_empty() && shapes.iter().all(Value::is_array) {
+        true  => shapes.iter().map(|variant| parse_variant(variant.as_array().unwrap())).collect::<Result<_, _>>()?,
+        false => vec![parse_variant(shapes)?],
+    };
+    Ok(Pending { def, recipe: Recipe { variables, count, templates } })
+}
+
+// One line of shape, e.g. 'quad (0.5 - t) 0.0  (0.5 + t) 1.0'
+fn parse_shape(line: &str) -> Result<(String, Vec<Expr>), String> {
+    let (kind, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    // Split it up at spaces, but not ones inside brackets
+    let mut words = Vec::new();
+    let mut depth = 0;
+    let mut word = String::new();
+    for c in rest.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => {
+                if !word.is_empty() { words.push(std::mem::take(&mut word)); }
+                continue;
+            }
+            _ => {}
+        }
+        word.push(c);
+    }
+    if !word.is_empty() { words.push(word); }
+
+    check_numbers(kind, words.len())?;
+    let numbers = words.iter().map(|word| Expr::parse(word)).collect::<Result<_, _>>()?;
+    Ok((String::from(kind), numbers))
+}
+
+// Makes sure the shape exists and has the right amount of numbers
+fn check_numbers(kind: &str, count: usize) -> Result<(), String> {
+    let expected: &[usize] = match kind {
+        "line_x" | "line_y" | "circle" => &[3],
+        // The 5th and 6th are where the texture starts and ends
+        "line" => &[4, 6],
+        // The 5th is how far it's turned
+        "quad" => &[4, 5],
+        _ => return Err(format!("unknown shape '{kind}'")),
+    };
+    match expected.contains(&count) {
+        true  => Ok(()),
+        false => Err(format!("{kind} needs {} numbers, not {count}", expected.iter().map(usize::to_string).collect::<Vec<_>>().join(" or "))),
+    }
+}
+
+// Makes a shape from its name and numbers
+fn build_shapes(kind: &str, numbers: &[f64]) -> Result<Vec<Shape>, String> {
+    check_numbers(kind, numbers.len())?;
+    let n = numbers;
+    Ok(match kind {
+        "line_x" => vec![Shape::LineX(n[0], n[1], n[2])],
+        "line_y" => vec![Shape::LineY(n[0], n[1], n[2])],
+        "line" if n.len() == 6 => vec![Shape::TexturedLine(n[0], n[1], n[2], n[3], n[4], n[5])],
+        "line"   => vec![Shape::Line(n[0], n[1], n[2], n[3])],
+        "circle" => vec![Shape::Circle(n[0], n[1], n[2])],
+        _ => vec![Shape::quad(n[0], n[1], n[2], n[3], n.get(4).copied().unwrap_or(0.0))],
+    })
+}
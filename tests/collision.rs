@@ -0,0 +1,32 @@
+// Tests for bumping into things
+
+use nalgebra::{point, Point2};
+use raycaster::game::{collision::{self, Segm}, player::PLAYER_RADIUS, Game};
+
+#[test]
+fn disks_get_pushed_out_of_circles() {
+    let circle = Segm::Circle(1.0, 1.0, 0.5);
+    assert!(collision::collide_disk_with_segment(point![2.0, 1.0], &circle).is_none());
+
+    let hit = collision::collide_disk_with_segment(point![1.6, 1.0], &circle).unwrap();
+    assert!((hit.depth - (0.5 + PLAYER_RADIUS - 0.6)).abs() < 1e-9, "{}", hit.depth);
+    assert!((hit.normal - point![1.0, 0.0]).magnitude() < 1e-9, "{:?}", hit.normal);
+
+    // Right in the middle still gets out somehow
+    let hit = collision::collide_disk_with_segment(point![1.0, 1.0], &circle).unwrap();
+    assert!(hit.depth > 0.0 && hit.normal.x.is_finite());
+}
+
+#[test]
+fn round_pillars_are_solid() {
+    let game = Game::from_map("res/images/map3.png").unwrap();
+    let middle: Point2<f64> = point![10.5, 3.5];
+    assert!(game.map.collision.contains(&Segm::Circle(10.5, 3.5, 0.3)));
+
+    // Walking straight into it
+    let mut pos = point![10.5, 4.5];
+    for _ in 0..20 {
+        collision::slide_mov(&mut pos, point![0.0, -0.05], &game.map.collision);
+    }
+    assert!(nalgebra::distance(&pos, &middle) >= 0.3 + PLAYER_RADIUS - 1e-6, "{pos:?}");
+}
@@ -0,0 +1,178 @@
+// Golden image tests for the renderer.
+// Renders a bunch of fixed camera poses and compares them to the reference PNGs in tests/golden/.
+// If something changes on purpose, re-bless the references with:
+//     UPDATE_GOLDEN=1 cargo test --test golden
+// On a failure the actual frame and a diff image (mismatched pixels in red) get written to target/golden-diff/.
+
+use std::path::{Path, PathBuf};
+
+use image::{Rgba, RgbaImage};
+use nalgebra::Vector2;
+use raycaster::{game::{sprite::Sprite, Game, LAMP_TEXTURE_INDEX}, renderer::{self, RenderConfig}};
+
+// How far apart a channel can be before the pixel counts as different
+const CHANNEL_TOLERANCE: u8 = 2;
+// How many pixels are allowed to be different before the test fails
+const MAX_MISMATCHED_PIXELS: usize = 0;
+
+fn check(name: &str, map_path: &str, pos: (f64, f64), dir: (f64, f64), pitch: f64, show_map: bool) {
+    check_with_config(name, &RenderConfig::default(), map_path, pos, dir, pitch, show_map);
+}
+
+fn check_with_config(name: &str, config: &RenderConfig, map_path: &str, pos: (f64, f64), dir: (f64, f64), pitch: f64, show_map: bool) {
+    let mut game = Game::from_map(map_path).unwrap();
+    let actual = renderer::render_offscreen(&mut game, config, Vector2::new(pos.0, pos.1), Vector2::new(dir.0, dir.1), pitch, show_map);
+
+    let golden_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{name}.png"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+        actual.save(&golden_path).unwrap();
+        return;
+    }
+
+    let expected = match image::open(&golden_path) {
+        Ok(img) => img.to_rgba8(),
+        Err(err) => panic!("couldn't open {}: {err} (run with UPDATE_GOLDEN=1 to create it)", golden_path.display()),
+    };
+    assert_eq!(expected.dimensions(), actual.dimensions(), "{name}: frame size changed");
+
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut mismatched = 0;
+    for ((e, a), d) in expected.pixels().zip(actual.pixels()).zip(diff.pixels_mut()) {
+        let different = e.0.iter().zip(a.0).any(|(&e, a)| e.abs_diff(a) > CHANNEL_TOLERANCE);
+        *d = match different {
+            true => {
+                mismatched += 1;
+                Rgba([255, 0, 0, 255])
+            }
+            // Faded version of the frame so you can tell where the differences are
+            false => Rgba([a[0] / 4, a[1] / 4, a[2] / 4, 255]),
+        };
+    }
+
+    if mismatched > MAX_MISMATCHED_PIXELS {
+        let out_dir = diff_dir();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        actual.save(out_dir.join(format!("{name}.actual.png"))).unwrap();
+        diff  .save(out_dir.join(format!("{name}.diff.png"))).unwrap();
+        panic!("{name}: {mismatched} pixels differ from {} (see {})", golden_path.display(), out_dir.display());
+    }
+}
+
+fn diff_dir() -> PathBuf {
+    let target = std::env::var_os("CARGO_TARGET_DIR").map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("target"));
+    target.join("golden-diff")
+}
+
+#[test]
+fn map3_start() {
+    check("map3_start", "res/images/map3.png", (13.0, 4.0), (1.0, 0.0), 0.0, false);
+}
+
+#[test]
+fn map3_elevator_door() {
+    check("map3_elevator_door", "res/images/map3.png", (12.5, 5.5), (-1.0, 0.0), 0.0, false);
+}
+
+#[test]
+fn map3_thin_wall() {
+    check("map3_thin_wall", "res/images/map3.png", (12.5, 4.5), (-0.3, -1.0), 0.0, false);
+}
+
+#[test]
+fn map3_thick_walls() {
+    check("map3_thick_walls", "res/images/map3.png", (14.5, 20.5), (1.0, 0.6), 0.0, false);
+}
+
+#[test]
+fn map3_pillars() {
+    check("map3_pillars", "res/images/map3.png", (12.5, 12.5), (1.0, 0.2), 0.0, false);
+}
+
+#[test]
+fn map3_diagonals() {
+    check("map3_diagonals", "res/images/map3.png", (3.5, 3.5), (-1.0, -0.8), 0.0, false);
+}
+
+#[test]
+fn map3_looking_up() {
+    check("map3_looking_up", "res/images/map3.png", (20.5, 5.5), (0.7, 1.0), -80.0, false);
+}
+
+#[test]
+fn map4_looking_down() {
+    check("map4_looking_down", "res/images/map4.png", (8.5, 3.5), (-1.0, 0.3), 90.0, false);
+}
+
+#[test]
+fn map4_overview() {
+    check("map4_overview", "res/images/map4.png", (2.5, 4.5), (1.0, 0.3), 0.0, true);
+}
+
+#[test]
+fn map3_square_aspect() {
+    check_with_config("map3_square_aspect", &RenderConfig::new(200, 200, 1, 2.0), "res/images/map3.png", (12.5, 12.5), (1.0, 0.2), 0.0, false);
+}
+
+// Drawing the columns on lots of threads has to look exactly the same as drawing them one by one
+#[test]
+fn parallel_matches_serial() {
+    let serial = RenderConfig { parallel: false, ..RenderConfig::default() };
+    let parallel = RenderConfig::default();
+
+    for (pos, dir) in [((13.0, 4.0), (1.0, 0.0)), ((12.5, 12.5), (1.0, 0.2)), ((14.5, 20.5), (-0.4, -1.0))] {
+        let mut game = Game::from_map("res/images/map3.png").unwrap();
+        let a = renderer::render_offscreen(&mut game, &serial,   Vector2::new(pos.0, pos.1), Vector2::new(dir.0, dir.1), 20.0, false);
+        let b = renderer::render_offscreen(&mut game, &parallel, Vector2::new(pos.0, pos.1), Vector2::new(dir.0, dir.1), 20.0, false);
+        assert!(a == b, "parallel frame doesn't match the serial one at {pos:?}");
+    }
+}
+
+// Out past the edge of the map there's no light to look up, so sprites out there are at full brightness like the floor and ceiling
+#[test]
+fn sprites_outside_the_map_are_lit() {
+    let config = RenderConfig::default();
+    let (pos, dir) = (Vector2::new(-2.0, 4.0), Vector2::new(-1.0, 0.0));
+    let mut game = Game::from_map("res/images/map3.png").unwrap();
+    game.sprites.clear();
+    let empty = renderer::render_offscreen(&mut game, &config, pos, dir, 0.0, false);
+    game.sprites.push(Sprite::new(Vector2::new(-4.0, 4.0), 0.7, LAMP_TEXTURE_INDEX));
+    let with_lamp = renderer::render_offscreen(&mut game, &config, pos, dir, 0.0, false);
+
+    // Only the bits that glow would show up if it wasn't lit, everything else would be practically black
+    let lamp: Vec<&Rgba<u8>> = empty.pixels().zip(with_lamp.pixels()).filter(|(e, l)| e != l).map(|(_, l)| l).collect();
+    assert!(!lamp.is_empty(), "the lamp didn't get drawn");
+    let dark = lamp.iter().filter(|pixel| pixel.0[0..3].iter().all(|&c| c < 24)).count();
+    assert_eq!(dark, 0, "{dark} of the lamp's {} pixels are black", lamp.len());
+}
+
+#[test]
+fn map4_grate() {
+    check("map4_grate", "res/images/map4.png", (7.5, 4.6), (-1.0, -0.3), 0.0, false);
+}
+
+// Two holograms one behind the other, blended over the grates at the far end
+#[test]
+fn map4_hologram() {
+    check("map4_hologram", "res/images/map4.png", (2.5, 4.4), (1.0, 0.05), 0.0, false);
+}
+
+// Low counters you can see the tops of, with a wall twice as tall behind them
+#[test]
+fn map4_wall_heights() {
+    check("map4_wall_heights", "res/images/map4.png", (7.5, 7.5), (1.0, 0.6), 0.0, false);
+}
+
+// Red alarm light lighting up a small room
+#[test]
+fn map4_coloured_light() {
+    check("map4_coloured_light", "res/images/map4.png", (4.5, 7.3), (0.2, 1.0), 0.0, false);
+}
+
+// The old look, with every cell lit flat
+#[test]
+fn map4_banded_light() {
+    let config = RenderConfig { smooth_lighting: false, ..RenderConfig::default() };
+    check_with_config("map4_banded_light", &config, "res/images/map4.png", (2.5, 4.4), (1.0, 0.3), 60.0, false);
+}
@@ -0,0 +1,255 @@
+// Tests for how light spreads around the map
+
+use std::{path::Path, sync::atomic::{AtomicUsize, Ordering}};
+
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+use nalgebra::Vector2;
+use raycaster::game::{lights::{DynamicLight, LightAnimation}, map::{DoorState, Map}, Game};
+
+fn light_at(map: &Map, x: usize, y: usize) -> [u8; 3] {
+    map.lightmap[map.coord_to_index(&x, &y)]
+}
+
+// Makes a little corridor map with a light at one end and 'middle' (a map colour) in the middle, then loads it
+fn corridor(name: &str, middle: [u8; 3]) -> Map {
+    corridor_with_settings(name, middle, "")
+}
+
+// Same as corridor() but with a settings file next to it
+fn corridor_with_settings(name: &str, middle: [u8; 3], settings: &str) -> Map {
+    let mut img = RgbaImage::from_pixel(7, 3, Rgba([255, 255, 255, 255]));
+    for x in 1..6 {
+        img.put_pixel(x, 1, Rgba([0, 0, 0, 255]));
+    }
+    img.put_pixel(1, 1, Rgba([255, 255, 0, 255]));
+    img.put_pixel(3, 1, Rgba([middle[0], middle[1], middle[2], 255]));
+
+    let path = std::env::temp_dir().join(format!("raycaster-lightmap-{name}.png"));
+    img.save(&path).unwrap();
+    std::fs::write(path.with_extension("txt"), settings).unwrap();
+    Map::load(path.to_string_lossy().into_owned()).unwrap()
+}
+
+#[test]
+fn light_fades_with_distance() {
+    let map = corridor("empty", [0, 0, 0]);
+    assert_eq!(light_at(&map, 1, 1), [16; 3]);
+    assert_eq!(light_at(&map, 5, 1), [12; 3]);
+    // Walls don't get any
+    assert_eq!(light_at(&map, 1, 0), [0; 3]);
+}
+
+#[test]
+fn thin_walls_block_light() {
+    // Grate running north to south, so light from the west lights up its cell but can't get past
+    let map = corridor("thin-wall", [150, 150, 150]);
+    assert_eq!(light_at(&map, 3, 1), [14; 3]);
+    assert_eq!(light_at(&map, 4, 1), [0; 3]);
+}
+
+#[test]
+fn light_gets_around_pillars() {
+    let map = corridor("pillar", [0, 174, 255]);
+    assert_eq!(light_at(&map, 5, 1), [12; 3]);
+}
+
+// The room under the door at (4, 6) on map4 only has a red light in it, white light from the corridor can only get in when the door's open
+#[test]
+fn doors_block_light_until_open() {
+    let mut map = Map::load(String::from("res/images/map4.png")).unwrap();
+    let door = map.coord_to_index(&4, &6);
+    assert_eq!(light_at(&map, 4, 7)[1], 0, "light got through a closed door");
+
+    *map.doors.get_mut(&door).unwrap() = DoorState::Opening(0.25);
+    map.calculate_lightmap();
+    assert_eq!(light_at(&map, 4, 7)[1], 0, "light got through a door that's only half open");
+
+    *map.doors.get_mut(&door).unwrap() = DoorState::Open(5.0);
+    map.calculate_lightmap();
+    assert!(light_at(&map, 4, 7)[1] > 0, "light didn't get through an open door");
+}
+
+// A big map with a bit of everything scattered around it (always the same one), for checking the lightmap on something busier than the real maps
+fn generated_map() -> Map {
+    // Every colour Map::load knows about that makes something, with plenty of empty space
+    let colours: [[u8; 3]; 16] = [
+        [0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+        [255, 255, 255], [255, 255, 255], [255, 255, 0], [255, 0, 0], [0, 128, 255],
+        [127, 81, 25], [150, 150, 150], [188, 96, 188], [0, 174, 255], [0, 128, 0],
+    ];
+    let mut seed: u32 = 12345;
+    let mut img = RgbaImage::new(64, 64);
+    for pixel in img.pixels_mut() {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let [r, g, b] = colours[(seed >> 16) as usize % colours.len()];
+        *pixel = Rgba([r, g, b, 255]);
+    }
+    // Tests run at the same time, so each one gets its own file
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!("raycaster-lightmap-generated-{}-{}.png", std::process::id(), COUNT.fetch_add(1, Ordering::Relaxed)));
+    img.save(&path).unwrap();
+    Map::load(path.to_string_lossy().into_owned()).unwrap()
+}
+
+// Baking the lightmap has to give exactly the same result as it always has on all of the maps.
+// The references are the raw lightmaps (r, g, b for each cell) in tests/golden/, re-bless them with UPDATE_GOLDEN=1 like the renderer tests
+#[test]
+fn lightmaps_match_references() {
+    for name in ["map", "map_1", "map2", "map3", "map4", "generated"] {
+        let map = match name {
+            "generated" => generated_map(),
+            _ => Map::load(format!("res/images/{name}.png")).unwrap(),
+        };
+        let actual: Vec<u8> = map.lightmap.iter().flatten().copied().collect();
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{name}.lightmap"));
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, &actual).unwrap();
+            continue;
+        }
+        let expected = std::fs::read(&path).unwrap_or_else(|err| panic!("couldn't open {}: {err}", path.display()));
+        assert!(expected == actual, "{name}: lightmap doesn't match {}", path.display());
+    }
+}
+
+// Redoing the light around a door has to end up the same as baking the whole map again
+#[test]
+fn updates_match_full_bake() {
+    let mut map = generated_map();
+    let mut doors: Vec<usize> = map.doors.keys().copied().collect();
+    doors.sort_unstable();
+    assert!(!doors.is_empty());
+
+    for &door in doors.iter().step_by(10) {
+        *map.doors.get_mut(&door).unwrap() = DoorState::Open(5.0);
+        map.update_lightmap_around(door);
+    }
+    let updated = map.lightmap.clone();
+    map.calculate_lightmap();
+    assert!(updated == map.lightmap, "opening doors one at a time didn't match baking it all at once");
+
+    for &door in doors.iter().step_by(20) {
+        *map.doors.get_mut(&door).unwrap() = DoorState::Closing(0.5);
+        map.update_lightmap_around(door);
+    }
+    let updated = map.lightmap.clone();
+    map.calculate_lightmap();
+    assert!(updated == map.lightmap, "closing doors one at a time didn't match baking it all at once");
+}
+
+// Dynamic lights go on top of the baked light without changing it, and disappear once they're gone
+#[test]
+fn dynamic_lights_add_on_top() {
+    let mut map = corridor("dynamic", [0, 0, 0]);
+    map.update_dynamic_light(&[DynamicLight::point(Vector2::new(5.5, 1.5), [2.0, 0.0, 8.0])]);
+    assert_eq!(map.light(map.coord_to_index(&5, &1)), [14, 12, 16]);
+    assert_eq!(map.light(map.coord_to_index(&4, &1)), [14, 13, 16]);
+    assert_eq!(light_at(&map, 5, 1), [12; 3], "dynamic light got baked in");
+
+    map.update_dynamic_light(&[]);
+    assert_eq!(map.light(map.coord_to_index(&5, &1)), [12; 3]);
+}
+
+#[test]
+fn dynamic_lights_are_blocked_like_baked_ones() {
+    let mut map = corridor("dynamic-thin-wall", [150, 150, 150]);
+    map.update_dynamic_light(&[DynamicLight::point(Vector2::new(5.5, 1.5), [16.0; 3])]);
+    assert_eq!(map.light(map.coord_to_index(&4, &1)), [15; 3]);
+    assert_eq!(map.light(map.coord_to_index(&2, &1)), light_at(&map, 2, 1), "light got through the grate");
+}
+
+#[test]
+fn spotlights_only_light_where_they_point() {
+    let mut map = corridor("spotlight", [0, 0, 0]);
+    let torch = DynamicLight::spotlight(Vector2::new(3.5, 1.5), Vector2::new(1.0, 0.0), 0.35, [4.0; 3]);
+    map.update_dynamic_light([&torch]);
+    assert!(map.dynamic_lightmap[map.coord_to_index(&5, &1)][0] > 0, "nothing in front of the torch got lit");
+    assert_eq!(map.dynamic_lightmap[map.coord_to_index(&1, &1)], [0; 3], "the torch lit up behind itself");
+}
+
+// Light cells that flicker aren't baked in, they get added each frame by Game::update_lights() instead
+#[test]
+fn flickering_lights_are_dynamic() {
+    let mut game = Game::from_map("res/images/map4.png").unwrap();
+    let flicker = game.map.coord_to_index(&13, &7);
+    assert_eq!(LightAnimation::from_flags(game.map.get(flicker).flags), LightAnimation::Flicker);
+    let baked = game.map.lightmap[flicker];
+
+    game.update_lights(0.0);
+    assert!(game.map.light(flicker)[0] > baked[0], "flickering light doesn't show up");
+
+    // It doesn't stay the same brightness
+    let brightness: Vec<f64> = (0..20).map(|i| LightAnimation::Flicker.brightness(i as f64 * 0.1, flicker)).collect();
+    assert!(brightness.iter().all(|b| (0.0..=1.0).contains(b)));
+    assert!(brightness.iter().any(|&b| b != brightness[0]));
+}
+
+#[test]
+fn bursts_fade_out() {
+    let mut game = Game::from_map("res/images/map4.png").unwrap();
+    game.update_lights(0.0);
+    let cell = game.map.coord_to_index(&4, &8);
+    let before = game.map.light(cell);
+
+    game.lights.push(DynamicLight::burst(Vector2::new(4.5, 8.5), [0.0, 16.0, 0.0], 0.2));
+    game.update_lights(0.05);
+    assert_eq!(game.map.light(cell)[1], 12);
+    game.update_lights(0.1);
+    assert_eq!(game.map.light(cell)[1], 4);
+    game.update_lights(0.1);
+    assert!(game.lights.is_empty(), "burst didn't go away");
+    assert_eq!(game.map.light(cell), before);
+}
+
+// A greyscale lightmap for the corridor maps with one bright cell, saved next to them
+fn paint_corridor_lightmap(name: &str) {
+    let mut painted = GrayImage::new(7, 3);
+    painted.put_pixel(5, 1, Luma([128]));
+    painted.save(std::env::temp_dir().join(format!("raycaster-lightmap-{name}.png"))).unwrap();
+}
+
+#[test]
+fn painted_light_adds_on() {
+    paint_corridor_lightmap("painted-add-light");
+    let map = corridor_with_settings("painted-add", [0, 0, 0], "lightmap = raycaster-lightmap-painted-add-light.png");
+    assert_eq!(light_at(&map, 5, 1), [16; 3]);
+    assert_eq!(light_at(&map, 4, 1), [13; 3]);
+}
+
+#[test]
+fn painted_light_replaces() {
+    paint_corridor_lightmap("painted-replace-light");
+    let mut map = corridor_with_settings("painted-replace", [0, 0, 0], "lightmap = raycaster-lightmap-painted-replace-light.png\nlightmap_mode = replace");
+    assert_eq!(light_at(&map, 5, 1), [8; 3]);
+    assert_eq!(light_at(&map, 1, 1), [0; 3], "the light on the map still lit things up");
+    // Even after redoing bits of it
+    map.update_lightmap_around(map.coord_to_index(&3, &1));
+    assert_eq!(light_at(&map, 1, 1), [0; 3]);
+}
+
+// Painted light doesn't end up in a saved lightmap, only the light from the map's own lights
+#[test]
+fn exported_lightmaps_leave_out_painted_light() {
+    paint_corridor_lightmap("painted-export-light");
+    let painted = corridor_with_settings("painted-export", [0, 0, 0], "lightmap = raycaster-lightmap-painted-export-light.png");
+    let unpainted = corridor("painted-export-none", [0, 0, 0]);
+    let path = std::env::temp_dir().join("raycaster-lightmap-painted-export-saved.png");
+    painted.save_lightmap(path.to_str().unwrap()).unwrap();
+    unpainted.save_lightmap(path.with_extension("unpainted.png").to_str().unwrap()).unwrap();
+    assert!(image::open(&path).unwrap() == image::open(path.with_extension("unpainted.png")).unwrap(), "the painted light got saved too");
+}
+
+// Saving a lightmap and loading it back in gives exactly the same light
+#[test]
+fn exported_lightmaps_load_back_the_same() {
+    let map = Map::load(String::from("res/images/map4.png")).unwrap();
+    let dir = std::env::temp_dir().join("raycaster-lightmap-export");
+    std::fs::create_dir_all(&dir).unwrap();
+    map.save_lightmap(dir.join("light.png").to_str().unwrap()).unwrap();
+
+    // A copy of the map that's lit entirely by the saved lightmap
+    std::fs::copy("res/images/map4.png", dir.join("map.png")).unwrap();
+    std::fs::write(dir.join("map.txt"), "lightmap = light.png\nlightmap_mode = replace\n").unwrap();
+    let reloaded = Map::load(dir.join("map.png").to_string_lossy().into_owned()).unwrap();
+    assert!(reloaded.lightmap == map.lightmap, "lightmap changed going through an image");
+}
@@ -0,0 +1,106 @@
+// Tests for what rays hit
+
+use image::{Rgba, RgbaImage};
+use nalgebra::Vector2;
+use raycaster::{game::Game, util::{self, shape::ShapeRegistry}};
+
+// Every surface a ray hits faces back towards the ray, however it's hit
+#[test]
+fn normals_face_the_ray() {
+    for (map, pos) in [("res/images/map3.png", (12.5, 12.5)), ("res/images/map3.png", (3.5, 3.5)), ("res/images/map4.png", (7.5, 4.6))] {
+        let mut game = Game::from_map(map).unwrap();
+        let pos = Vector2::new(pos.0, pos.1);
+        for i in 0..360 {
+            let dir = Vector2::new((i as f64).to_radians().cos(), (i as f64).to_radians().sin());
+            // Looking straight down the ray, so the distances are along it
+            game.player.dir = dir;
+            for (cell, distance, _, normal, _) in util::raycast_all(&game, pos, dir, 100.0, 0.5) {
+                assert!((normal.magnitude() - 1.0).abs() < 1e-9, "{map}: normal {normal:?} isn't 1 long");
+                assert!(normal.dot(&dir) < 0.0, "{map}: normal {normal:?} of cell {cell} faces away from the ray {dir:?}");
+                // Diagonals face diagonally, round pillars face out from their middle, everything else on these maps lines up with the grid
+                match game.map.get(cell).kind {
+                    8 => assert!((normal.x.abs() - normal.y.abs()).abs() < 1e-9, "{map}: diagonal normal {normal:?}"),
+                    7 => {
+                        let (x, y) = game.map.index_to_coord(cell);
+                        let hit = pos + dir * distance;
+                        let out = (hit - Vector2::new(x as f64 + 0.5, y as f64 + 0.5)).normalize();
+                        assert!((normal.dot(&out).abs() - 1.0).abs() < 1e-6, "{map}: round pillar normal {normal:?} should be {out:?}");
+                    }
+                    _ => assert!(normal.x == 0.0 || normal.y == 0.0, "{map}: normal {normal:?} of cell {cell} isn't on the grid"),
+                }
+            }
+        }
+    }
+}
+
+// Round pillars get hit on their edge, with the texture going round them
+#[test]
+fn rays_hit_round_pillars() {
+    let mut game = Game::from_map("res/images/map3.png").unwrap();
+    game.player.dir = Vector2::new(0.0, -1.0);
+    let pillar = game.map.coord_to_index(&10, &3);
+
+    let (cell, distance, along, normal, _) = util::raycast(&game, Vector2::new(10.5, 5.5), Vector2::new(0.0, -1.0), 100.0).unwrap();
+    assert_eq!(cell, pillar);
+    assert!((distance - 1.7).abs() < 1e-9, "{distance}");
+    assert!((normal - Vector2::new(0.0, 1.0)).magnitude() < 1e-9, "{normal:?}");
+    // A quarter of the way round, and a 0.3 radius pillar fits the texture round twice
+    assert!((along - 0.5).abs() < 1e-9, "{along}");
+
+    // Just missing it
+    let (cell, ..) = util::raycast(&game, Vector2::new(10.85, 5.5), Vector2::new(0.0, -1.0), 100.0).unwrap();
+    assert_ne!(cell, pillar);
+}
+
+// One tall wall somewhere on the map shouldn't make every ray go all the way to the edge of it.
+// A row of normal walls with a taller one right behind the first, and the tallest one at the far end
+#[test]
+fn rays_stop_once_nothing_can_poke_out() {
+    let mut img = RgbaImage::from_pixel(40, 3, Rgba([0, 0, 0, 255]));
+    for x in 3..40 {
+        img.put_pixel(x, 1, Rgba([255, 255, 255, 255]));
+    }
+    // The alpha is how tall it is, in eighths of a block
+    img.put_pixel(4, 1, Rgba([255, 255, 255, 12]));
+    img.put_pixel(39, 1, Rgba([255, 255, 255, 16]));
+    let path = std::env::temp_dir().join(format!("raycaster-raycast-tall-{}.png", std::process::id()));
+    img.save(&path).unwrap();
+    let mut game = Game::from_map(&path.to_string_lossy()).unwrap();
+    game.player.dir = Vector2::new(1.0, 0.0);
+
+    let hits = util::raycast_all(&game, Vector2::new(1.5, 1.5), Vector2::new(1.0, 0.0), 100.0, 0.5);
+    let cells: Vec<usize> = hits.iter().map(|hit| hit.0).collect();
+    assert_eq!(cells[..2], [game.map.coord_to_index(&3, &1), game.map.coord_to_index(&4, &1)]);
+    assert!(hits.len() < 8, "the ray went {} walls deep", hits.len());
+    assert!(!cells.contains(&game.map.coord_to_index(&39, &1)));
+}
+
+// Turning a line turns its texture with it, so the same bit of the line has the same bit of texture whichever way it faces
+#[test]
+fn turned_lines_keep_their_texture_the_right_way_round() {
+    let img = RgbaImage::from_pixel(3, 3, Rgba([0, 0, 0, 255]));
+    let path = std::env::temp_dir().join(format!("raycaster-raycast-turned-{}.png", std::process::id()));
+    img.save(&path).unwrap();
+    let mut game = Game::from_map(&path.to_string_lossy()).unwrap();
+    let (registry, errors) = ShapeRegistry::parse("
+        10 : Bar : 1
+            line 0.5 0.0  0.5 1.0
+    ");
+    assert!(errors.is_empty(), "{errors:?}");
+    game.map.shapes = registry;
+    let middle = game.map.coord_to_index(&1, &1);
+    game.map.cells[middle].kind = 10;
+
+    // Facing the first way, 0.2 of the way down the line (the rays are a bit slanted, lines can't be hit by perfectly straight ones)
+    game.player.dir = Vector2::new(1.0, 0.1);
+    let (cell, _, along, ..) = util::raycast(&game, Vector2::new(0.5, 1.1), game.player.dir, 10.0).unwrap();
+    assert_eq!(cell, middle);
+    assert!((along - 0.2).abs() < 1e-9, "{along}");
+
+    // Turned clockwise, that bit of the line is now 0.2 in from the right
+    game.map.cells[middle].flags = 1;
+    game.player.dir = Vector2::new(0.1, 1.0);
+    let (cell, _, along, ..) = util::raycast(&game, Vector2::new(1.7, 0.5), game.player.dir, 10.0).unwrap();
+    assert_eq!(cell, middle);
+    assert!((along - 0.2).abs() < 1e-9, "{along}");
+}
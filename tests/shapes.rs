@@ -0,0 +1,325 @@
+// Tests for loading shapes from the shapes file
+
+use raycaster::{game::map::{DoorState, Map}, util::shape::{Bitfield, DirectionMode, Shape, ShapeRegistry, SHAPES_PATH}};
+
+#[test]
+fn shapes_file_loads_cleanly() {
+    let source = std::fs::read_to_string(SHAPES_PATH).unwrap();
+    let (registry, errors) = ShapeRegistry::parse(&source);
+    assert!(errors.is_empty(), "{errors:?}");
+    for kind in [3, 4, 5, 6, 7, 8] {
+        assert!(registry.def(kind).is_some(), "shape {kind} is missing");
+    }
+}
+
+#[test]
+fn parses_shapes_and_variants() {
+    let (registry, errors) = ShapeRegistry::parse("
+        // A comment
+        10 : Thing : 0
+            // Anything before the first '#' is the first variant
+            line_x 0.5  0.0 1.0  // another comment
+            line 0.0 0.0  1.0 1.0
+            # second variant
+            circle 0.5 0.5  0.25
+            quad 0.25 0.25  0.75 0.75
+    ");
+    assert!(errors.is_empty(), "{errors:?}");
+
+    let def = registry.def(10).unwrap();
+    assert_eq!(def.name, "Thing");
+    assert_eq!(def.direction_mode, DirectionMode::Fixed);
+    assert_eq!(def.variants.len(), 2);
+    assert_eq!(def.variants[0], [Shape::LineX(0.5, 0.0, 1.0), Shape::Line(0.0, 0.0, 1.0, 1.0)]);
+    assert_eq!(def.variants[1], [Shape::Circle(0.5, 0.5, 0.25), Shape::Quad([(0.25, 0.25), (0.75, 0.25), (0.75, 0.75), (0.25, 0.75)])]);
+
+    // The variant is the top 6 bits of the flags
+    assert_eq!(registry.get(10, 0b0000_0000).len(), 2);
+    assert_eq!(registry.get(10, 0b0000_0100).len(), 2);
+    assert!(registry.get(10, 0b0000_1000).is_empty());
+    assert!(registry.get(11, 0).is_empty());
+}
+
+#[test]
+fn shapes_face_the_way_their_flags_say() {
+    let (registry, _) = ShapeRegistry::parse("
+        1 : Turns : 1
+            line 0.0 0.0  1.0 0.5
+        2 : Flips : 2
+            line_x 0.25  0.0 1.0
+    ");
+    assert_eq!(registry.get(1, 0), [Shape::Line(0.0, 0.0, 1.0, 0.5)]);
+    assert_eq!(registry.get(1, 1), [Shape::Line(1.0, 0.0, 0.5, 1.0)]);
+    assert_eq!(registry.get(1, 2), [Shape::Line(1.0, 1.0, 0.0, 0.5)]);
+    assert_eq!(registry.get(1, 3), [Shape::Line(0.0, 1.0, 0.5, 0.0)]);
+
+    assert_eq!(registry.get(2, 0), [Shape::LineX(0.25, 0.0, 1.0)]);
+    assert_eq!(registry.get(2, 1), [Shape::LineY(0.25, 0.0, 1.0)]);
+    // Only the lowest bit matters for N/S or E/W
+    assert_eq!(registry.get(2, 2), registry.get(2, 0));
+}
+
+#[test]
+fn bad_lines_are_reported_and_skipped() {
+    let (registry, errors) = ShapeRegistry::parse("
+        line_x 0.5 0.0 1.0
+        3 : Thing
+        4 : Thing : 0
+            line_x 0.5 0.0
+            triangle 0 0 1
+            line_y 0.5 0.0 nope
+            line_y 0.5 0.0 1.0
+    ");
+    assert_eq!(errors.len(), 5, "{errors:?}");
+    assert!(errors[0].starts_with("line 2:"), "{errors:?}");
+    assert!(registry.def(3).is_none());
+    assert_eq!(registry.get(4, 0), [Shape::LineY(0.5, 0.0, 1.0)]);
+}
+
+#[test]
+fn lines_under_a_bad_header_dont_go_in_the_shape_before() {
+    let (registry, errors) = ShapeRegistry::parse("
+        1 : A : 0
+            line_x 0.5  0.0 1.0
+        2 : B
+            line_y 0.5  0.0 1.0
+    ");
+    assert_eq!(errors.len(), 2, "{errors:?}");
+    assert!(errors[1].starts_with("line 5:"), "{errors:?}");
+    assert_eq!(registry.get(1, 0), [Shape::LineX(0.5, 0.0, 1.0)]);
+    assert!(registry.def(2).is_none());
+}
+
+#[test]
+fn named_bitfields_come_out_of_the_flags() {
+    let (registry, errors) = ShapeRegistry::parse("
+        3 : Door : 0 : orientation 1, flipped 1, type 2
+        4 : Wall : 2
+            line_x 0.5  0.0 1.0
+    ");
+    assert!(errors.is_empty(), "{errors:?}");
+
+    let door = registry.def(3).unwrap();
+    assert_eq!(door.bitfields, [Bitfield::new("orientation", 0, 1), Bitfield::new("flipped", 1, 1), Bitfield::new("type", 2, 2)]);
+    assert_eq!(door.fields(0b0000_0111), [("orientation", 1), ("flipped", 1), ("type", 1)]);
+    assert_eq!(registry.field(3, 0b0000_1000, "type"), 2);
+    assert_eq!(registry.field(3, 0b0000_1000, "nope"), 0);
+
+    // Shapes that don't name any get rotation and variant
+    assert_eq!(registry.field(4, 0b0000_0101, "rotation"), 1);
+    assert_eq!(registry.field(4, 0b0000_0101, "variant"), 1);
+}
+
+#[test]
+fn json_shapes_load_into_the_same_registry() {
+    let source = std::fs::read_to_string("res/shape_test.json").unwrap();
+    let (registry, errors) = ShapeRegistry::parse_json(&source);
+    assert!(errors.is_empty(), "{errors:?}");
+
+    let diagonal = registry.def(8).unwrap();
+    assert_eq!(diagonal.name, "Diagonal");
+    assert_eq!(diagonal.direction_mode, DirectionMode::FourWay);
+    assert_eq!(registry.get(8, 1), [Shape::Line(1.0, 0.0, 0.0, 1.0)]);
+
+    // Same as the text version
+    let (text, _) = ShapeRegistry::parse(&std::fs::read_to_string(SHAPES_PATH).unwrap());
+    for flags in [0b0000_0000, 0b0000_0001, 0b0000_1100, 0b0001_0000] {
+        assert_eq!(registry.get(5, flags), text.get(5, flags), "flags {flags:08b}");
+    }
+    assert_eq!(registry.def(3).unwrap().bitfields, text.def(3).unwrap().bitfields);
+    // Every kind of door, every way round
+    for flags in 0..16 {
+        assert_eq!(registry.get_open(3, flags, 0.25), text.get_open(3, flags, 0.25), "flags {flags:08b}");
+    }
+}
+
+#[test]
+fn doors_move_as_they_open() {
+    let (registry, errors) = ShapeRegistry::parse(&std::fs::read_to_string(SHAPES_PATH).unwrap());
+    assert!(errors.is_empty(), "{errors:?}");
+
+    // Loaded shut
+    assert_eq!(registry.get_open(3, 0, 0.0), registry.get(3, 0));
+    assert_eq!(registry.get_open(3, 0, 0.5), [Shape::Line(0.6, -0.5, 0.6, 0.5), Shape::Line(0.4, -0.5, 0.4, 0.5), Shape::LineY(0.5, 0.4, 0.6)]);
+    // E/W doors get flipped like everything else, the long sides keep their texture stretched along them
+    assert_eq!(registry.get_open(3, 0b0000_0001, 0.5), [Shape::Line(-0.5, 0.6, 0.5, 0.6), Shape::Line(-0.5, 0.4, 0.5, 0.4), Shape::LineX(0.5, 0.4, 0.6)]);
+    // Opening the other way
+    assert_eq!(registry.get_open(3, 0b0000_0010, 0.5)[0], Shape::Line(0.6, 0.5, 0.6, 1.5));
+    // Elevator doors split down the middle, each half having half the texture
+    assert_eq!(registry.get_open(3, 0b0000_0100, 0.5)[..2], [Shape::TexturedLine(0.55, -0.25, 0.55, 0.25, 0.0, 0.5), Shape::TexturedLine(0.45, -0.25, 0.45, 0.25, 0.0, 0.5)]);
+    assert_eq!(registry.get_open(3, 0b0000_0100, 1.0)[2], Shape::TexturedLine(0.55, 1.0, 0.55, 1.5, 0.5, 1.0));
+
+    // Shapes that don't move are the same however open they are
+    assert_eq!(registry.get_open(4, 1, 0.5), registry.get(4, 1));
+}
+
+#[test]
+fn maps_keep_their_doors_shapes_up_to_date() {
+    let mut map = Map::load(String::from("res/images/map4.png")).unwrap();
+    let door = map.coord_to_index(&4, &6);
+    let (kind, flags) = (map.cells[door].kind, map.cells[door].flags);
+    assert_eq!(map.cell_shapes(door), map.shapes.get(kind, flags));
+
+    *map.doors.get_mut(&door).unwrap() = DoorState::Opening(0.5);
+    map.update_doors(0.25);
+    assert_eq!(map.cell_shapes(door), map.shapes.get_open(kind, flags, 0.5));
+    map.update_doors(0.5);
+    assert_eq!(map.cell_shapes(door), map.shapes.get_open(kind, flags, 1.0));
+}
+
+#[test]
+fn bad_json_shapes_are_reported_and_skipped() {
+    let (registry, errors) = ShapeRegistry::parse_json(r#"{
+        "1": { "name": "Fine", "shapes": [ { "line_y": [0.5, 0.0, 1.0] } ] },
+        "2": { "shapes": [] },
+        "3": { "name": "Huge", "bitfields": { "a": { "length": 6 }, "b": { "length": 6 } } },
+        "4": { "name": "Typo", "shapes": [ { "lime": [0.5, 0.0, 1.0] } ] },
+        "nope": { "name": "Bad id" }
+    }"#);
+    assert_eq!(errors.len(), 4, "{errors:?}");
+    assert!(errors[0].starts_with("shape 2:"), "{errors:?}");
+    assert_eq!(registry.get(1, 0), [Shape::LineY(0.5, 0.0, 1.0)]);
+    for kind in [2, 3, 4] {
+        assert!(registry.def(kind).is_none());
+    }
+
+    let (_, errors) = ShapeRegistry::parse_json("{ not json");
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn variants_have_to_fit_in_the_variant_bitfield() {
+    let (registry, errors) = ShapeRegistry::parse("
+        1 : Small : 0 : rotation 2, variant 1
+            variants: 3
+            line_x 0.5  0.0 1.0
+        2 : Fits : 0 : rotation 2, variant 1
+            variants: 2
+            line_x 0.5  0.0 1.0
+    ");
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    assert!(errors[0].starts_with("line 3:") && errors[0].contains("1 to 2"), "{errors:?}");
+    assert_eq!(registry.def(2).unwrap().variants.len(), 2);
+
+    let (registry, errors) = ShapeRegistry::parse_json(r#"{
+        "1": { "name": "Small", "bitfields": { "variant": { "length": 1 } }, "variants": 3, "shapes": [ { "line_y": [0.5, 0.0, 1.0] } ] },
+        "2": { "name": "None", "bitfields": { "rotation": { "length": 2 } }, "variants": 2, "shapes": [ { "line_y": [0.5, 0.0, 1.0] } ] }
+    }"#);
+    assert_eq!(errors.len(), 2, "{errors:?}");
+    assert!(registry.def(1).is_none() && registry.def(2).is_none());
+}
+
+#[test]
+fn variables_are_worked_out_for_each_variant() {
+    let (registry, errors) = ShapeRegistry::parse("
+        5 : ThickWall : 2
+            variants: 4
+            variables:
+                t = (variant + 1) / 10
+                far = 1 - -t * 2 + 0.5 * 2  // 2 + 2t
+            quad (0.5 - t) 0.0  (0.5 + t) 1.0
+            line_x far  0 1
+        6 : Each : 0
+            line_x variant  0 1
+            # the variant number still comes from its place in the file
+            line_x (variant / 4)  0 1
+    ");
+    assert!(errors.is_empty(), "{errors:?}");
+
+    let def = registry.def(5).unwrap();
+    assert_eq!(def.variants.len(), 4);
+    for (variant, shapes) in def.variants.iter().enumerate() {
+        let t = (variant as f64 + 1.0) / 10.0;
+        assert!(shapes.contains(&Shape::Quad([(0.5 - t, 0.0), (0.5 + t, 0.0), (0.5 + t, 1.0), (0.5 - t, 1.0)])), "{shapes:?}");
+        assert!(shapes.contains(&Shape::LineX(1.0 + t * 2.0 + 1.0, 0.0, 1.0)), "{shapes:?}");
+    }
+    // Still turns like any other shape
+    assert!(registry.get(5, 0b0000_1001).contains(&Shape::Quad([(0.0, 0.2), (0.0, 0.8), (1.0, 0.8), (1.0, 0.2)])));
+
+    assert_eq!(registry.get(6, 0b0000_0000), [Shape::LineX(0.0, 0.0, 1.0)]);
+    assert_eq!(registry.get(6, 0b0000_0100), [Shape::LineX(0.25, 0.0, 1.0)]);
+}
+
+#[test]
+fn bad_expressions_are_reported() {
+    let (registry, errors) = ShapeRegistry::parse("
+        1 : Thing : 0
+            variants: 2
+            a = nope + 1
+            2b = 3
+            line_x (0.5 0.0 1.0
+            line_x 0.5 * 0.0 1.0
+            line_x missing 0 1
+            line_y 0.5 0 1
+        2 : Both : 0
+            variants: 2
+            line_x 0.5 0 1
+            #
+            line_y 0.5 0 1
+    ");
+    // Mistakes in variables and shapes only come up once, even though they're worked out for both variants
+    assert_eq!(errors.len(), 6, "{errors:?}");
+    assert!(errors.iter().any(|err| err.starts_with("line 4:")), "{errors:?}");
+    assert!(errors.iter().any(|err| err.starts_with("line 8:") && err.contains("missing")), "{errors:?}");
+    assert_eq!(registry.get(1, 0b0000_0100), [Shape::LineY(0.5, 0.0, 1.0)]);
+    assert_eq!(registry.get(2, 0b0000_0100), [Shape::LineX(0.5, 0.0, 1.0)]);
+}
+
+#[test]
+fn quads_turn_and_flip_as_a_whole() {
+    let (registry, errors) = ShapeRegistry::parse("
+        1 : Box : 1
+            quad 0.0 0.0  0.5 0.25
+        2 : Turned : 0
+            quad 0.25 0.0  0.75 1.0  90
+            # not a quarter turn
+            quad 0.25 0.25  0.75 0.75  45
+    ");
+    assert!(errors.is_empty(), "{errors:?}");
+    assert_eq!(registry.get(1, 1), [Shape::Quad([(1.0, 0.0), (1.0, 0.5), (0.75, 0.5), (0.75, 0.0)])]);
+    // Turning all the way round gets back to the start
+    assert_eq!(Shape::quad(0.125, 0.25, 0.375, 0.5, 0.0).turned().turned().turned().turned(), Shape::quad(0.125, 0.25, 0.375, 0.5, 0.0));
+    // Flipping a thin wall's quad gives the E/W one
+    assert_eq!(Shape::quad(0.4, 0.0, 0.6, 1.0, 0.0).flipped(), Shape::Quad([(0.0, 0.4), (0.0, 0.6), (1.0, 0.6), (1.0, 0.4)]));
+
+    // A quarter turn lands exactly on the grid
+    assert_eq!(registry.get(2, 0), [Shape::Quad([(1.0, 0.25), (1.0, 0.75), (0.0, 0.75), (0.0, 0.25)])]);
+    let Shape::Quad(corners) = registry.get(2, 0b0000_0100)[0] else { panic!() };
+    for (x, y) in corners {
+        let from_middle = ((x - 0.5).powi(2) + (y - 0.5).powi(2)).sqrt();
+        assert!((from_middle - 0.125f64.sqrt()).abs() < 1e-9, "{corners:?}");
+    }
+    // A diamond's corners are straight out from the middle
+    assert!(corners.iter().any(|&(x, y)| (x - 0.5).abs() < 1e-9 && y < 0.5), "{corners:?}");
+
+    let (_, errors) = ShapeRegistry::parse("
+        3 : Bad : 0
+            quad 0.0 0.0  1.0
+            quad 0.0 0.0  1.0 1.0  45 2
+    ");
+    assert_eq!(errors.len(), 2, "{errors:?}");
+    assert!(errors[0].contains("4 or 5"), "{errors:?}");
+}
+
+#[test]
+fn lines_keep_their_texture_when_turned() {
+    let (registry, errors) = ShapeRegistry::parse("
+        1 : Slats : 1
+            line 0.25 0.0  0.25 1.0
+            line 0.75 0.0  0.75 1.0  0.0 0.5
+            line_x 0.5  0.0 1.0
+    ");
+    assert!(errors.is_empty(), "{errors:?}");
+    // Lines stay lines with the texture stretched along them, only LineX/LineY line up with the cell
+    assert_eq!(registry.get(1, 1), [
+        Shape::Line(1.0, 0.25, 0.0, 0.25),
+        Shape::TexturedLine(1.0, 0.75, 0.0, 0.75, 0.0, 0.5),
+        Shape::LineY(0.5, 1.0, 0.0),
+    ]);
+
+    let (_, errors) = ShapeRegistry::parse("
+        2 : Bad : 0
+            line 0.0 0.0  1.0 1.0  0.5
+    ");
+    assert!(errors.len() == 1 && errors[0].contains("4 or 6"), "{errors:?}");
+}
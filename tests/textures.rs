@@ -0,0 +1,18 @@
+// Tests for loading textures
+
+use raycaster::game::{texture::{Texture, Transparency}, Game};
+
+#[test]
+fn only_colour_keyed_textures_get_holes() {
+    // The map image has a magenta pixel (the player spawn), it's used for thin walls so it mustn't have a hole in it
+    let map = Texture::from_file("res/images/map3.png");
+    assert_eq!(map.transparency, Transparency::Opaque);
+    assert_eq!(map.with_colour_key().transparency, Transparency::CutOut);
+
+    let game = Game::new();
+    for index in 0..=6 {
+        assert_eq!(game.textures[index].transparency, Transparency::Opaque, "texture {index}");
+    }
+    assert_eq!(game.textures[7].transparency, Transparency::CutOut);
+    assert_eq!(game.textures[8].transparency, Transparency::CutOut);
+}